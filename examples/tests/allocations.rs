@@ -0,0 +1,57 @@
+static mut COUNTER: i32 = 0;
+
+fn independent_locals() -> i32 {
+    let mut a = 1;
+    let mut b = 2;
+    let x = &mut b;
+    let y = &mut a; // Does not invalidate `x`, it borrows a different allocation
+    *x += 1;
+    *y += 1;
+    a + b
+}
+
+fn static_pointer() -> i32 {
+    unsafe {
+        let raw = &mut COUNTER as *mut i32;
+        let x = &mut *raw;
+        COUNTER = 1; // Pops the tag of `x` in the stack of `COUNTER`
+        *x = 2; // Undefined behavior
+        COUNTER
+    }
+}
+
+fn heap_pointer() -> i32 {
+    let mut b = Box::new(1);
+    let raw = &mut *b as *mut i32;
+    unsafe { *raw = 2 };
+    *b
+}
+
+fn vec_buffer() -> i32 {
+    let mut v: Vec<i32> = Vec::with_capacity(2);
+    v.push(1);
+    let p = v.as_mut_ptr();
+    let q = v.as_mut_ptr(); // Both pointers are derived from the buffer of `v`
+    unsafe {
+        *p = 2;
+        *q += 1;
+    }
+    v[0]
+}
+
+fn box_into_raw() -> i32 {
+    let b = Box::new(1);
+    let raw = Box::into_raw(b);
+    let x = unsafe { &mut *raw };
+    unsafe { *raw = 2 }; // Pops the tag of `x` in the stack of the heap allocation
+    *x += 1; // Undefined behavior
+    unsafe { *Box::from_raw(raw) }
+}
+
+fn main() {
+    assert_eq!(independent_locals(), 5);
+    assert_eq!(static_pointer(), 2);
+    assert_eq!(heap_pointer(), 2);
+    assert_eq!(vec_buffer(), 3);
+    assert_eq!(box_into_raw(), 3);
+}
@@ -0,0 +1,52 @@
+fn add_to(a: &mut i32, b: &mut i32) -> i32 {
+    *a += *b;
+    *a
+}
+
+fn disjoint_fields() -> i32 {
+    let mut pair = (1, 2);
+    let raw = &mut pair as *mut (i32, i32);
+    unsafe { add_to(&mut (*raw).0, &mut (*raw).1) } // Different fields, the arguments do not alias
+}
+
+fn same_pointer_in_fields() -> i32 {
+    let mut a = 1;
+    let raw = &mut a as *mut i32;
+    let pair = (raw, raw);
+    unsafe { add_to(&mut *pair.0, &mut *pair.1) } // Both fields point to `a`, the arguments alias
+}
+
+fn array_elements(i: usize, j: usize) -> i32 {
+    let mut values = [1, 2];
+    let raw = &mut values as *mut [i32; 2];
+    unsafe { add_to(&mut (*raw)[i], &mut (*raw)[j]) } // The arguments alias only when `i == j`
+}
+
+fn read_both(a: &i32, b: &i32) -> i32 {
+    *a + *b
+}
+
+fn shared_arguments() -> i32 {
+    let x = 1;
+    read_both(&x, &x) // Shared references may alias
+}
+
+fn three_arguments(a: &mut i32, b: &mut i32, c: &mut i32) -> i32 {
+    *a + *b + *c
+}
+
+fn third_argument_aliases() -> i32 {
+    let mut a = 1;
+    let mut b = 2;
+    let raw = &mut a as *mut i32;
+    unsafe { three_arguments(&mut *raw, &mut b, &mut *raw) } // The first and the third arguments alias
+}
+
+fn main() {
+    assert_eq!(disjoint_fields(), 3);
+    assert_eq!(same_pointer_in_fields(), 2);
+    assert_eq!(array_elements(0, 1), 3);
+    assert_eq!(shared_arguments(), 2);
+    assert_eq!(third_argument_aliases(), 4);
+    assert_eq!(vec![1, 2].len(), 2);
+}
@@ -0,0 +1,75 @@
+trait Target {
+    fn set(&mut self, ptr: *mut i32);
+}
+
+struct Holder {
+    ptr: *mut i32,
+}
+
+struct Counter {
+    count: i32,
+}
+
+impl Target for Holder {
+    fn set(&mut self, ptr: *mut i32) {
+        self.ptr = ptr;
+    }
+}
+
+impl Target for Counter {
+    fn set(&mut self, _ptr: *mut i32) {
+        self.count += 1;
+    }
+}
+
+fn set_ptr(out: &mut *mut i32, value: *mut i32) {
+    *out = value;
+}
+
+fn keep_ptr(_out: &mut *mut i32, _value: *mut i32) {}
+
+fn function_pointer(flag: bool) -> i32 {
+    let mut x = 1;
+    let mut y = 2;
+    let mut p = &mut x as *mut i32;
+    let f: fn(&mut *mut i32, *mut i32) = if flag { set_ptr } else { keep_ptr };
+    f(&mut p, &mut y); // May call `set_ptr`
+    let r = unsafe { &mut *p };
+    y += 1; // Pops the tag of `r` if `p` points to `y`
+    *r += 1; // Undefined behavior
+    x
+}
+
+fn dynamic(target: &mut dyn Target) -> i32 {
+    let mut y = 2;
+    target.set(&mut y); // Calls the method of `Holder` or `Counter`
+    y
+}
+
+fn boxed_dynamic() -> i32 {
+    let mut x = 1;
+    let mut y = 2;
+    let mut holder = Holder { ptr: &mut x };
+    let target: &mut dyn Target = &mut holder;
+    target.set(&mut y);
+    let r = unsafe { &mut *holder.ptr };
+    y += 1; // Pops the tag of `r`, `holder.ptr` may point to `y` now
+    *r += 1; // Undefined behavior
+    x
+}
+
+fn closure() -> i32 {
+    let mut x = 1;
+    let add = |v: &mut i32| *v += 1;
+    add(&mut x);
+    let callback: &dyn Fn(&mut i32) = &add;
+    callback(&mut x);
+    x
+}
+
+fn main() {
+    function_pointer(true);
+    dynamic(&mut Counter { count: 0 });
+    boxed_dynamic();
+    closure();
+}
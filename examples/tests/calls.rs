@@ -0,0 +1,46 @@
+fn swap_ptrs(a: &mut *mut i32, b: &mut *mut i32) {
+    let t = *a;
+    *a = *b;
+    *b = t;
+}
+
+fn set_ptr(out: &mut *mut i32, value: *mut i32) {
+    *out = value;
+}
+
+fn make() -> Box<i32> {
+    Box::new(1)
+}
+
+fn swapped() -> i32 {
+    let mut x = 1;
+    let mut y = 2;
+    let mut p = &mut x as *mut i32;
+    let mut q = &mut y as *mut i32;
+    swap_ptrs(&mut p, &mut q);
+    let r = unsafe { &mut *p };
+    y += 1; // Pops the tag of `r`, `p` points to `y` now
+    *r += 1; // Undefined behavior
+    x
+}
+
+fn stored() -> i32 {
+    let mut x = 1;
+    let mut y = 2;
+    let mut p = &mut x as *mut i32;
+    set_ptr(&mut p, &mut y);
+    unsafe { *p += 1 };
+    x + y
+}
+
+fn boxed() -> i32 {
+    let mut b = make();
+    *b += 1;
+    *b
+}
+
+fn main() {
+    assert_eq!(swapped(), 1);
+    assert_eq!(stored(), 4);
+    assert_eq!(boxed(), 2);
+}
@@ -0,0 +1,68 @@
+use std::cell::{Cell, RefCell};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Counter {
+    step: i32,
+    total: Cell<i32>,
+}
+
+fn add_both(a: &Cell<i32>, b: &Cell<i32>) {
+    a.set(1);
+    b.set(a.get() + 1);
+}
+
+fn cells() -> i32 {
+    let c = Cell::new(0);
+    add_both(&c, &c); // Both references may write the cell
+    let r1 = &c;
+    let r2 = &c;
+    Cell::set(r1, 2);
+    r2.get()
+}
+
+fn fields() -> i32 {
+    let counter = Counter { step: 2, total: Cell::new(0) };
+    let r1 = &counter;
+    let r2 = &counter;
+    r1.total.set(r2.step);
+    r2.total.set(r1.total.get() + r1.step);
+    counter.total.get()
+}
+
+fn ref_cell() -> i32 {
+    let c = RefCell::new(1);
+    let r1 = &c;
+    let r2 = &c;
+    *r1.borrow_mut() += 1;
+    *r2.borrow_mut() += 1;
+    let value = *r1.borrow();
+    value
+}
+
+fn locks() -> usize {
+    let counter = AtomicUsize::new(0);
+    let m = Mutex::new(0);
+    let (r1, r2) = (&counter, &counter);
+    r1.fetch_add(1, Ordering::SeqCst);
+    r2.fetch_add(1, Ordering::SeqCst);
+    *m.lock().unwrap() += 1;
+    r1.load(Ordering::SeqCst)
+}
+
+fn cell_after_unique() -> i32 {
+    let mut c = Cell::new(1);
+    let raw = &c as *const Cell<i32>;
+    let unique = &mut c; // Pops the tag of `raw`
+    unique.set(2);
+    unsafe { (*raw).set(3) }; // Undefined behavior
+    c.get()
+}
+
+fn main() {
+    cells();
+    fields();
+    ref_cell();
+    locks();
+    cell_after_unique();
+}
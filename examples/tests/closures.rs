@@ -0,0 +1,79 @@
+#![feature(generators, generator_trait)]
+
+use std::ops::Generator;
+use std::pin::Pin;
+
+fn apply<F: FnMut(i32)>(mut f: F) {
+    f(1);
+}
+
+fn captured_by_reference() -> i32 {
+    let mut x = 1;
+    let r = &mut x;
+    let raw = r as *mut i32;
+    let inc = || unsafe { *raw += 1 };
+    *r += 1; // Pops the tag of `raw`
+    inc(); // Undefined behavior, the closure writes through `raw`
+    x
+}
+
+fn captured_by_move() -> i32 {
+    let mut x = 1;
+    let r = &mut x;
+    let raw = r as *mut i32;
+    let add = move |v: i32| unsafe { *raw += v };
+    *r += 1; // Pops the tag of `raw`
+    add(2); // Undefined behavior
+    x
+}
+
+fn passed_to_generic() -> i32 {
+    let mut x = 1;
+    let r = &mut x;
+    let raw = r as *mut i32;
+    *r += 1; // Pops the tag of `raw`
+    apply(|v| unsafe { *raw += v }); // Undefined behavior
+    x
+}
+
+fn captured_mutably() -> i32 {
+    let mut x = 1;
+    let mut y = 0;
+    let mut add = |v: i32, w: &mut i32| {
+        x += v;
+        *w += 1;
+    };
+    add(1, &mut y);
+    add(2, &mut y);
+    x + y
+}
+
+fn generator() -> i32 {
+    let mut x = 1;
+    let r = &mut x;
+    let raw = r as *mut i32;
+    let mut g = || {
+        unsafe { *raw += 1 };
+        yield 1;
+    };
+    *r += 1; // Pops the tag of `raw`
+    Pin::new(&mut g).resume(()); // Undefined behavior
+    x
+}
+
+fn iterators() -> i32 {
+    let v = vec![1, 2, 3];
+    let mut total = 0;
+    v.iter().for_each(|n| total += n);
+    let doubled: i32 = v.iter().map(|n| n * 2).filter(|n| *n > 2).sum();
+    total + doubled
+}
+
+fn main() {
+    captured_by_reference();
+    captured_by_move();
+    passed_to_generic();
+    captured_mutably();
+    generator();
+    iterators();
+}
@@ -0,0 +1,44 @@
+enum Shape {
+    Empty,
+    Pair { x: i32, y: i32 },
+}
+
+fn variant_fields(e: &mut Shape) -> i32 {
+    match e {
+        Shape::Pair { x, y } => unsafe {
+            let raw = x as *mut i32;
+            *y += 1; // Another field of the variant, `raw` stays valid
+            *raw + *y
+        },
+        Shape::Empty => 0,
+    }
+}
+
+fn local_variant() -> i32 {
+    let mut s = Shape::Pair { x: 1, y: 2 };
+    if let Shape::Pair { x, y } = &mut s {
+        let ry = &mut *y;
+        *ry += *x;
+        *x += 1;
+        return *ry;
+    }
+    0
+}
+
+fn whole_enum_written() -> i32 {
+    let mut o = Some(1);
+    let raw = match &mut o {
+        Some(v) => v as *mut i32,
+        None => return 0,
+    };
+    o = None; // Writing the whole enum pops the pointers to its variants
+    unsafe { *raw } // Undefined behavior
+}
+
+fn main() {
+    let mut s = Shape::Pair { x: 1, y: 2 };
+    variant_fields(&mut s);
+    variant_fields(&mut Shape::Empty);
+    local_variant();
+    whole_enum_written();
+}
@@ -0,0 +1,23 @@
+#![crate_type = "lib"]
+// Library without `main`, analyze it with `--all` or `--unsafe`
+
+pub fn safe_sum(a: &i32, b: &i32) -> i32 {
+    *a + *b
+}
+
+pub fn reborrow_through_raw() -> i32 {
+    let mut local = 0;
+    let raw = &mut local as *mut i32;
+    unsafe {
+        let x = &mut *raw;
+        *raw = 1; // Pops the tag of `x`
+        *x = 2; // Undefined behavior
+    }
+    local
+}
+
+/// # Safety
+/// `ptr` must be valid for writes
+pub unsafe fn write_through(ptr: *mut i32, value: i32) {
+    *ptr = value;
+}
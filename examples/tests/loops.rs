@@ -0,0 +1,44 @@
+fn loop_reborrow() -> i32 {
+    let mut local = 0;
+    let raw = &mut local as *mut i32;
+    let mut i = 0;
+    while i < 10 {
+        let x = unsafe { &mut *raw };
+        *x += i;
+        i += 1;
+    }
+    local
+}
+
+fn branch_invalidates() -> i32 {
+    let mut local = 0;
+    let raw = &mut local as *mut i32;
+    let x = unsafe { &mut *raw };
+    if local == 0 {
+        local = 1; // Pops the tag of `x` only in this branch
+    }
+    *x = 2; // Undefined behavior when the branch above was taken
+    local
+}
+
+fn make() -> (i32, i32) {
+    (1, 2)
+}
+
+fn field_read_in_one_branch(c: bool) -> i32 {
+    let mut t = make();
+    let x = &mut t as *mut (i32, i32);
+    if c {
+        let _a = t.1 + t.0; // Only this branch gives the fields stacks of their own
+    } else {
+        t = make(); // Pops the tag of `x`
+    }
+    unsafe { (*x).0 = 7 } // Undefined behavior when the else branch was taken
+    t.0
+}
+
+fn main() {
+    assert_eq!(loop_reborrow(), 45);
+    assert_eq!(branch_invalidates(), 2);
+    assert_eq!(field_read_in_one_branch(true), 7);
+}
@@ -0,0 +1,27 @@
+fn write_through_raw(x: &mut i32, y: *mut i32) {
+    unsafe { *y = 1 }; // Pops the tag of `x`, which the call protects
+}
+
+fn read_after_write(x: &i32, y: *mut i32) -> i32 {
+    unsafe { *y = 1 }; // Pops the tag of `x`, which the call protects
+    0
+}
+
+fn write_through_reference(x: &mut i32, y: *mut i32) {
+    *x = 2;
+}
+
+fn main() {
+    let mut a = 0;
+    let y = &mut a as *mut i32;
+    let x = unsafe { &mut *y };
+    write_through_raw(x, y); // Undefined behavior, even if `x` is not used again
+
+    let mut b = 0;
+    let y = &mut b as *mut i32;
+    read_after_write(unsafe { &*y }, y); // Undefined behavior
+
+    let mut c = 0;
+    let y = &mut c as *mut i32;
+    write_through_reference(unsafe { &mut *y }, y);
+}
@@ -0,0 +1,40 @@
+fn round_trip() -> i32 {
+    let mut x = 1;
+    let p = &mut x as *mut i32;
+    let addr = p as usize;
+    let q = addr as *mut i32; // May be any pointer exposed as an integer
+    unsafe { *q = 2 };
+    x
+}
+
+fn bump(buffer: &mut [u8; 64], offset: usize) -> *mut u8 {
+    let base = buffer.as_mut_ptr() as usize; // Exposes the buffer to the caller
+    let aligned = (base + offset + 7) & !7;
+    aligned as *mut u8
+}
+
+fn allocator() -> u8 {
+    let mut buffer = [0u8; 64];
+    let a = bump(&mut buffer, 0);
+    let b = bump(&mut buffer, 8);
+    unsafe {
+        *a = 1;
+        *b = 2;
+        *a + *b
+    }
+}
+
+fn through_int_after_invalidation() -> i32 {
+    let mut x = 1;
+    let addr = &mut x as *mut i32 as usize;
+    let r = &mut x; // Pops the exposed pointer
+    *r = 3;
+    unsafe { *(addr as *mut i32) = 2 }; // UB: no exposed pointer can write `x`
+    *r
+}
+
+fn main() {
+    round_trip();
+    allocator();
+    through_int_after_invalidation();
+}
@@ -0,0 +1,41 @@
+fn reassigned_reference() -> i32 {
+    let mut a = 1;
+    let mut b = 2;
+    let mut r = &mut a;
+    *r += 1;
+    r = &mut b; // `r` no longer points to `a`
+    a += 1;
+    *r += 1;
+    a + b
+}
+
+fn reassigned_in_loop(values: &mut [i32; 4]) -> i32 {
+    let mut total = 0;
+    let mut p = &mut total as *mut i32;
+    unsafe { *p += 1 };
+    p = &mut values[0] as *mut i32;
+    for i in 0..4 {
+        p = &mut values[i] as *mut i32; // Replaces the pointer of the previous iteration
+        unsafe { *p += 1 };
+    }
+    total += 1; // Does not invalidate `p`, it points to an element of `values`
+    unsafe { *p += 1 };
+    total
+}
+
+fn reassigned_after_write() -> i32 {
+    let mut a = 1;
+    let mut p = &mut a as *mut i32;
+    let x = unsafe { &mut *p };
+    p = &mut a as *mut i32; // Pops the tag of `x` in the stack of `a`
+    unsafe { *p += 1 };
+    *x += 1; // Undefined behavior
+    a
+}
+
+fn main() {
+    let mut values = [1, 2, 3, 4];
+    assert_eq!(reassigned_reference(), 6);
+    assert_eq!(reassigned_in_loop(&mut values), 2);
+    assert_eq!(reassigned_after_write(), 3);
+}
@@ -0,0 +1,56 @@
+struct Node {
+    value: i32,
+    parent: *mut Node,
+    next: Option<Box<Node>>,
+}
+
+fn sum(node: &Node) -> i32 {
+    match &node.next {
+        Some(next) => node.value + sum(next),
+        None => node.value,
+    }
+}
+
+fn root(node: *mut Node) -> *mut Node {
+    unsafe {
+        if (*node).parent.is_null() { node } else { root((*node).parent) }
+    }
+}
+
+fn is_even(n: u32) -> bool {
+    if n == 0 { true } else { is_odd(n - 1) }
+}
+
+fn is_odd(n: u32) -> bool {
+    if n == 0 { false } else { is_even(n - 1) }
+}
+
+fn point_to(p: &mut *mut i32, q: *mut i32, n: u32) {
+    if n > 0 {
+        *p = q; // Only the recursive calls store the pointer
+        point_to(p, q, n - 1);
+    }
+}
+
+fn stored_by_recursion() -> i32 {
+    let mut x = 1;
+    let mut y = 2;
+    let mut p = &mut x as *mut i32;
+    point_to(&mut p, &mut y, 3);
+    let r = unsafe { &mut *p };
+    y += 1; // Pops the tag of `r`, `p` points to `y` now
+    *r += 1; // Undefined behavior
+    x + y
+}
+
+fn main() {
+    let mut a = Node { value: 1, parent: std::ptr::null_mut(), next: None };
+    let parent = &mut a as *mut Node;
+    let b = Node { value: 2, parent, next: None };
+    let mut c = Node { value: 3, parent: std::ptr::null_mut(), next: Some(Box::new(b)) };
+    assert_eq!(sum(&c), 5);
+    let last = &mut c as *mut Node;
+    assert!(root(last) == last);
+    assert!(is_even(10));
+    assert_eq!(stored_by_recursion(), 5);
+}
@@ -0,0 +1,80 @@
+use std::ops::Deref;
+
+trait Target {
+    fn set(&mut self, ptr: *mut i32);
+}
+
+struct Holder {
+    ptr: *mut i32,
+}
+
+impl Target for Holder {
+    fn set(&mut self, ptr: *mut i32) {
+        self.ptr = ptr;
+    }
+}
+
+fn set_generic<T: Target>(target: &mut T, ptr: *mut i32) {
+    target.set(ptr);
+}
+
+struct Cell {
+    value: i32,
+}
+
+struct Wrapper {
+    inner: Cell,
+}
+
+impl Deref for Wrapper {
+    type Target = Cell;
+    fn deref(&self) -> &Cell {
+        &self.inner
+    }
+}
+
+struct Reset<'a> {
+    target: &'a mut i32,
+}
+
+impl<'a> Drop for Reset<'a> {
+    fn drop(&mut self) {
+        *self.target = 0;
+    }
+}
+
+fn trait_method() -> i32 {
+    let mut x = 1;
+    let mut y = 2;
+    let mut holder = Holder { ptr: &mut x };
+    set_generic(&mut holder, &mut y); // Resolved to `<Holder as Target>::set`
+    let r = unsafe { &mut *holder.ptr };
+    y += 1; // Pops the tag of `r`, `holder.ptr` points to `y` now
+    *r += 1; // Undefined behavior
+    x
+}
+
+fn deref() -> i32 {
+    let mut w = Wrapper { inner: Cell { value: 1 } };
+    let p = &mut w as *mut Wrapper;
+    let c: &Cell = unsafe { &**p }; // Borrows `w.inner` through `Deref::deref`
+    unsafe { (*p).inner.value = 2 };
+    c.value // Undefined behavior
+}
+
+fn dropped() -> i32 {
+    let mut x = 1;
+    {
+        let reset = Reset { target: &mut x };
+        *reset.target += 1;
+    } // Runs `<Reset as Drop>::drop`
+    let v = vec![1, 2, 3];
+    let total: i32 = v.iter().map(|n| n + x).filter(|n| *n > 2).sum();
+    total
+}
+
+fn main() {
+    trait_method();
+    deref();
+    dropped();
+}
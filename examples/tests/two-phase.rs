@@ -0,0 +1,50 @@
+struct Counter {
+    n: usize,
+}
+
+impl Counter {
+    fn add(&mut self, v: usize) {
+        self.n += v;
+    }
+
+    fn get(&self) -> usize {
+        self.n
+    }
+}
+
+fn vec_push() -> usize {
+    let mut v = vec![1];
+    v.push(v.len()); // The receiver is reserved while `v.len()` reads it
+    v.len()
+}
+
+fn method_call(c: &mut Counter) {
+    c.add(c.get());
+}
+
+fn write_during_reservation() -> usize {
+    let mut c = Counter { n: 1 };
+    let raw = &mut c as *mut Counter;
+    // Reserving `c` for the call does not write it, `raw` is still valid
+    c.add(unsafe {
+        (*raw).n = 2;
+        1
+    });
+    c.n
+}
+
+fn read_after_activation() -> usize {
+    let mut c = Counter { n: 1 };
+    let raw = &mut c as *mut Counter;
+    c.add(unsafe { (*raw).n }); // The call writes through the reserved borrow and pops `raw`
+    unsafe { (*raw).n } // Undefined behavior
+}
+
+fn main() {
+    vec_push();
+    let mut c = Counter { n: 0 };
+    method_call(&mut c);
+    c.add(c.get());
+    write_during_reservation();
+    read_after_activation();
+}
@@ -1,23 +1,246 @@
 use crate::mir_visitor::body_visitor::{MirVisitor};
-use rustc_middle::ty::{TyCtxt};
+use crate::diagnostics::{Checker, Diagnostic};
+
+use crate::points_to::{AliasAnalysis, PlacePath, PointsToGraph};
+use crate::andersen::Andersen;
+use crate::steensgaard::Steensgaard;
+use crate::summaries::{FunctionSummary, SummaryCache};
+use crate::call_graph::CallGraph;
+use rustc_hir::def::DefKind;
+use rustc_hir::def_id::DefId;
+use rustc_hir::Unsafety;
+use rustc_index::vec::IndexVec;
+use rustc_middle::mir::{AggregateKind, BasicBlock, Rvalue, StatementKind};
+use rustc_middle::ty::{Instance, ParamEnv, TyCtxt};
+use rustc_middle::ty::subst::InternalSubsts;
 use petgraph::dot::{Dot, Config};
+use log::{debug, info, warn};
+
+// Upper bound of times the functions of a cycle of calls are analyzed to compute their summaries
+const MAX_SUMMARY_ITERATIONS: usize = 8;
 
+// Which functions of the crate are analyzed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnalysisMode {
+    EntryFunction, // Only `main`, and the functions it calls
+    AllFunctions, // Every function of the crate with MIR available
+    UnsafeFunctions, // Only `unsafe fn`s and functions containing `unsafe` blocks
+    Functions(Vec<String>), // The functions with these paths, like `module::Type::method`
+}
 
-pub fn analyze<'tcx>(tcx: TyCtxt) {
-    let entry_fn_id;
-    match tcx.entry_fn(()) {
-        Some((def_id, _fn_type)) => { entry_fn_id = def_id;}
-        None => {
-            println!("The program must have a main function");
-            return;
+// Points-to analysis that answers the alias queries
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AliasBackend {
+    Andersen, // Inclusion based, precise and field sensitive
+    Steensgaard, // Unification based, with a single pass over the blocks. Faster for large crates but less precise
+}
+
+impl AliasBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AliasBackend::Andersen => "andersen",
+            AliasBackend::Steensgaard => "steensgaard",
         }
     }
 
-    if tcx.is_mir_available(entry_fn_id) {
-        let function_body = tcx.optimized_mir(entry_fn_id);
-        let mut visitor = MirVisitor::new(tcx,&function_body, Vec::new());
-        visitor.visit_body(function_body);
+    pub fn from_name(name: &str) -> Option<AliasBackend> {
+        match name {
+            "andersen" => Some(AliasBackend::Andersen),
+            "steensgaard" => Some(AliasBackend::Steensgaard),
+            _ => None,
+        }
+    }
+
+    pub fn new_analysis(&self, path_depth: usize) -> Box<dyn AliasAnalysis> {
+        match self {
+            AliasBackend::Andersen => Box::new(Andersen::new(path_depth)),
+            AliasBackend::Steensgaard => Box::new(Steensgaard::new()),
+        }
+    }
+}
+
+// Fields followed inside an allocation before the deeper places are merged into it
+pub const DEFAULT_PATH_DEPTH: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct AnalysisOptions {
+    pub mode: AnalysisMode,
+    pub checkers: Vec<Checker>,
+    pub path_depth: usize,
+    pub alias_backend: AliasBackend,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> AnalysisOptions {
+        AnalysisOptions {
+            mode: AnalysisMode::EntryFunction,
+            checkers: Checker::ALL.to_vec(),
+            path_depth: DEFAULT_PATH_DEPTH,
+            alias_backend: AliasBackend::Andersen,
+        }
+    }
+}
+
+// Findings of the analysis of one function
+pub struct FunctionAnalysis {
+    pub function: DefId,
+    pub name: String,
+    pub diagnostics: Vec<Diagnostic>,
+    pub alias_graph: PointsToGraph,
+    pub entry_graphs: IndexVec<BasicBlock, Option<PointsToGraph>>, // Points-to graph at the start of each basic block
+    pub points_to: Box<dyn AliasAnalysis>, // Solved points-to sets, to query if two places may alias
+}
+
+impl FunctionAnalysis {
+    // Places that `path` may point to at the start of `block`, none if the block is unreachable
+    pub fn targets_at(&self, block: BasicBlock, path: &PlacePath) -> Vec<PlacePath> {
+        match self.entry_graphs.get(block) {
+            Some(Some(graph)) => graph.targets(path),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Analyzes the functions selected by the options and returns the findings of the enabled checkers
+pub fn analyze(tcx: TyCtxt, options: &AnalysisOptions) -> Vec<FunctionAnalysis> {
+    let mut results: Vec<FunctionAnalysis> = Vec::new();
+    let mut summaries = SummaryCache::new();
+    for def_id in analyzed_functions(tcx, &options.mode) {
+        let mut result = analyze_function(tcx, def_id, options, &mut summaries);
+        result.diagnostics.retain(|diagnostic| options.checkers.contains(&diagnostic.kind.checker()));
+        // A finding inside a callee is already reported by the caller that reached it
+        result.diagnostics.retain(|diagnostic| {
+            !results.iter().any(|previous| previous.diagnostics.iter().any(|other| diagnostic.repeats(other)))
+        });
+        results.push(result);
+    }
+    results
+}
+
+// Analyzes one function. The summaries of the functions it calls are taken from `summaries`,
+// and the new ones are added to it, with the summary of the function itself.
+pub fn analyze_function<'tcx>(tcx: TyCtxt<'tcx>, def_id: DefId, options: &AnalysisOptions, summaries: &mut SummaryCache<'tcx>) -> FunctionAnalysis {
+    info!("Analyzing {}", tcx.def_path_str(def_id));
+    let instance = Instance::new(def_id, InternalSubsts::identity_for_item(tcx, def_id));
+    let param_env = tcx.param_env_reveal_all_normalized(def_id);
+    summarize_callees(tcx, instance, param_env, options, summaries);
+    let visitor = visit_function(tcx, instance, param_env, options, summaries);
+    summaries.insert(instance, visitor.summary());
+
+    debug!("{:?}", Dot::with_config(&visitor.alias_graph.graph, &[Config::EdgeNoLabel]));
+    FunctionAnalysis {
+        function: def_id,
+        name: tcx.def_path_str(def_id),
+        diagnostics: visitor.diagnostics,
+        alias_graph: visitor.alias_graph,
+        entry_graphs: visitor.entry_graphs,
+        points_to: visitor.points_to,
+    }
+}
+
+// Visits the body of a function with the summaries of the functions it calls, adding the new ones
+pub fn visit_function<'tcx>(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>, param_env: ParamEnv<'tcx>,
+    options: &AnalysisOptions, summaries: &mut SummaryCache<'tcx>) -> MirVisitor<'tcx> {
+    let mut visitor = MirVisitor::new(tcx, instance, param_env, options.clone());
+    visitor.summaries = std::mem::take(summaries);
+    visitor.visit_body(visitor.body);
+    *summaries = std::mem::take(&mut visitor.summaries);
+    visitor
+}
+
+// Summarizes the functions reachable from `entry`, callees first. The functions of a cycle start
+// with empty summaries and are analyzed again until their summaries do not change.
+fn summarize_callees<'tcx>(tcx: TyCtxt<'tcx>, entry: Instance<'tcx>, param_env: ParamEnv<'tcx>, options: &AnalysisOptions, summaries: &mut SummaryCache<'tcx>) {
+    for (component, recursive) in CallGraph::new(tcx, entry, param_env).components() {
+        // The entry itself is analyzed by the caller, unless it is part of a cycle
+        let is_entry = component == [entry];
+        if (is_entry && !recursive) || component.iter().all(|instance| summaries.get(*instance).is_some()) {
+            continue;
+        }
+        if recursive {
+            for instance in &component {
+                summaries.insert(*instance, FunctionSummary::default());
+            }
+        }
+        for iteration in 1..=MAX_SUMMARY_ITERATIONS {
+            let mut changed = false;
+            for instance in &component {
+                debug!("Summarizing {} (iteration {})", instance, iteration);
+                let summary = visit_function(tcx, *instance, param_env, options, summaries).summary();
+                changed |= summaries.get(*instance) != Some(&summary);
+                summaries.insert(*instance, summary);
+            }
+            if !changed || !recursive {
+                break;
+            }
+            if iteration == MAX_SUMMARY_ITERATIONS {
+                warn!("The summaries of {} did not converge", component[0]);
+            }
+        }
+    }
+}
+
+fn analyzed_functions(tcx: TyCtxt, mode: &AnalysisMode) -> Vec<DefId> {
+    if *mode == AnalysisMode::EntryFunction {
+        return match tcx.entry_fn(()) {
+            Some((def_id, _fn_type)) if tcx.is_mir_available(def_id) => vec![def_id],
+            Some(_) => Vec::new(),
+            None => {
+                warn!("The program must have a main function");
+                Vec::new()
+            }
+        };
+    }
+
+    let mut functions: Vec<DefId> = tcx.mir_keys(()).iter()
+        .map(|local_def_id| local_def_id.to_def_id())
+        .filter(|def_id| matches!(tcx.def_kind(*def_id), DefKind::Fn | DefKind::AssocFn))
+        .filter(|def_id| tcx.is_mir_available(*def_id))
+        // Skip the code generated by macros, like `#[derive]` implementations
+        .filter(|def_id| !tcx.def_span(*def_id).from_expansion())
+        .filter(|def_id| match mode {
+            AnalysisMode::UnsafeFunctions => uses_unsafe(tcx, *def_id),
+            AnalysisMode::Functions(paths) => paths.contains(&tcx.def_path_str(*def_id)),
+            _ => true,
+        })
+        .collect();
+
+    if let AnalysisMode::Functions(paths) = mode {
+        for path in paths {
+            if !functions.iter().any(|def_id| tcx.def_path_str(*def_id) == *path) {
+                warn!("Function {} not found", path);
+            }
+        }
+    }
+    // Definition order, so the results follow the source
+    functions.sort_by_key(|def_id| def_id.index);
+    functions
+}
+
+fn uses_unsafe(tcx: TyCtxt, def_id: DefId) -> bool {
+    if tcx.fn_sig(def_id).unsafety() == Unsafety::Unsafe {
+        return true;
+    }
+    has_unsafe_blocks(tcx, def_id)
+}
 
-        println!("{:?}", Dot::with_config(&visitor.alias_graph.graph, &[Config::EdgeNoLabel]));
+// The unsafe blocks of closures and generators are in their own bodies, so these are checked too
+fn has_unsafe_blocks(tcx: TyCtxt, def_id: DefId) -> bool {
+    let unsafety = tcx.unsafety_check_result(def_id.expect_local());
+    if !unsafety.unsafe_blocks.is_empty() {
+        return true;
     }
+    tcx.optimized_mir(def_id).basic_blocks().iter()
+        .flat_map(|block| &block.statements)
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::Assign(assignment) => match &assignment.1 {
+                Rvalue::Aggregate(kind, _) => match **kind {
+                    AggregateKind::Closure(nested, _) | AggregateKind::Generator(nested, ..) => Some(nested),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .any(|nested| nested.is_local() && has_unsafe_blocks(tcx, nested))
 }
@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::points_to::{AliasAnalysis, Constraint, PathElem, PlaceExpr, PlacePath};
+
+// Inclusion based points-to analysis. The constraints of the whole function are collected
+// first and then solved together, so the sets hold for every point of the function.
+#[derive(Clone, Debug, Default)]
+pub struct Andersen {
+    constraints: BTreeSet<Constraint>,
+    points_to: BTreeMap<PlacePath, BTreeSet<PlacePath>>,
+    path_depth: usize,
+}
+
+impl AliasAnalysis for Andersen {
+    fn add_constraint(&mut self, constraint: Constraint) {
+        self.constraints.insert(constraint);
+    }
+
+    // Applies the constraints until no points-to set grows
+    fn solve(&mut self) {
+        let constraints: Vec<Constraint> = self.constraints.iter().cloned().collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for constraint in &constraints {
+                changed |= self.apply(constraint);
+            }
+        }
+    }
+
+    // Places that the pointers stored in `path`, or in any part of it, may point to
+    fn points_to_set(&self, path: &PlacePath) -> BTreeSet<PlacePath> {
+        self.points_to.iter()
+            .filter(|(pointer, _)| pointer.overlaps(path))
+            .flat_map(|(_, targets)| targets.iter().cloned())
+            .collect()
+    }
+
+    // True if the pointers stored in `a` and `b` may point to overlapping places
+    fn may_alias(&self, a: &PlacePath, b: &PlacePath) -> bool {
+        let targets_a = self.points_to_set(a);
+        self.points_to_set(b).iter().any(|target| targets_a.iter().any(|other| other.overlaps(target)))
+    }
+}
+
+impl Andersen {
+    pub fn new(path_depth: usize) -> Andersen {
+        Andersen { constraints: BTreeSet::new(), points_to: BTreeMap::new(), path_depth }
+    }
+
+    fn apply(&mut self, constraint: &Constraint) -> bool {
+        let mut changed = false;
+        match constraint {
+            Constraint::AddressOf { dest, target } => {
+                let targets = self.resolve(target);
+                for dest in self.resolve(dest) {
+                    for target in &targets {
+                        changed |= self.points_to.entry(dest.clone()).or_default().insert(target.clone());
+                    }
+                }
+            }
+            Constraint::Copy { dest, source } | Constraint::Load { dest, source } | Constraint::Store { dest, source } => {
+                let sources = self.resolve(source);
+                for dest in self.resolve(dest) {
+                    for source in &sources {
+                        changed |= self.copy(&dest, source);
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    // The pointers stored in `source` and in its fields are copied to the same fields of `dest`.
+    // The ones stored in a place that contains `source` may be in any part of it.
+    fn copy(&mut self, dest: &PlacePath, source: &PlacePath) -> bool {
+        let copies: Vec<(PlacePath, BTreeSet<PlacePath>)> = self.points_to.iter()
+            .filter(|(pointer, _)| pointer.overlaps(source))
+            .map(|(pointer, targets)| {
+                let mut dest = dest.clone();
+                if source.contains(pointer) {
+                    for elem in &pointer.projections[source.projections.len()..] {
+                        dest = self.project(&dest, *elem);
+                    }
+                }
+                (dest, targets.clone())
+            })
+            .collect();
+
+        let mut changed = false;
+        for (dest, targets) in copies {
+            let set = self.points_to.entry(dest).or_default();
+            for target in targets {
+                changed |= set.insert(target);
+            }
+        }
+        changed
+    }
+
+    // Places that a place expression may be, with the current points-to sets
+    fn resolve(&self, place: &PlaceExpr) -> BTreeSet<PlacePath> {
+        let mut paths = BTreeSet::new();
+        paths.insert(place.base.clone());
+        for projections in &place.derefs {
+            paths = paths.iter()
+                .flat_map(|path| self.points_to_set(path))
+                .map(|mut path| {
+                    for elem in projections {
+                        path = self.project(&path, *elem);
+                    }
+                    path
+                })
+                .collect();
+        }
+        paths
+    }
+
+    fn project(&self, path: &PlacePath, elem: PathElem) -> PlacePath {
+        if path.projections.len() < self.path_depth { path.project(elem) } else { path.clone() }
+    }
+}
@@ -1,86 +1,288 @@
 #![feature(rustc_private)]
 
-extern crate rustc_error_codes;
-extern crate rustc_errors;
-extern crate rustc_hash;
-extern crate rustc_hir;
+extern crate rustc_driver;
 extern crate rustc_interface;
-extern crate rustc_session;
-extern crate rustc_span;
 
-use rustc_errors::registry;
-use rustc_hash::{FxHashMap, FxHashSet};
-use rustc_session::config;
+use rustc_driver::Compilation;
+use rustc_interface::{interface, Queries};
 use cargo_metadata::{MetadataCommand};
-// use rustc_span::source_map;
-use static_alias_analyzer::analyzer::analyze;
+use serde_json::{json, Value};
+use static_alias_analyzer::analyzer::{analyze, AnalysisMode};
+use static_alias_analyzer::cli::{self, flag_values, Options, OutputFormat};
+use static_alias_analyzer::sysroot::find_sysroot;
 
-use std::path;
-use std::path::PathBuf;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+// Analyzer options, set by `cargo rsaa` for the wrapper invocations
+const RSAA_ARGS: &str = "RSAA_ARGS";
+// Directory where each wrapper invocation writes the results of its target
+const RSAA_RESULTS: &str = "RSAA_RESULTS";
+
+fn usage() -> String {
+    format!("\
+Usage: cargo rsaa [OPTIONS] [CARGO CHECK OPTIONS]
+
+{}
+
+Package and target selection:
+    -p, --package <SPEC>      Analyze only this package, can be repeated
+    --workspace               Analyze all the packages of the workspace
+    --lib                     Analyze only the library of the selected packages
+    --bin <NAME>              Analyze only this binary
+    --example <NAME>          Analyze only this example
+    --tests                   Analyze the test targets too
+    --features <FEATURES>     Features to enable
+    --all-features            Enable all the features
+    --manifest-path <PATH>    Path to the Cargo.toml of the workspace or package
+
+Any other option, like --release, is also passed to `cargo check`.
+Binaries are analyzed from their `main` function and libraries function by function.
+Exits with code 1 when the analysis finds violations or the crate does not compile.", cli::OPTIONS)
+}
 
 fn main() {
-    let config = create_compiler_config();
-    run_compiler(config);
+    let args: Vec<String> = env::args().collect();
+
+    // Cargo calls the wrapper as `cargo-rsaa path/to/rustc [RUSTC ARGS]`
+    if args.len() > 1 && Path::new(&args[1]).file_stem().map_or(false, |stem| stem == "rustc") {
+        process::exit(run_wrapper(&args[1], &args[2..]));
+    }
+
+    // Cargo calls the subcommand as `cargo-rsaa rsaa [OPTIONS]`
+    let options = match cli::parse(args.into_iter().skip(1).skip_while(|arg| arg == "rsaa")) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, usage());
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", usage());
+        return;
+    }
+    process::exit(run_cargo_check(&options));
+}
+
+// Runs `cargo check` with this binary as the rustc wrapper of the workspace members, so
+// they are compiled with their exact arguments and dependencies are built by plain rustc
+fn run_cargo_check(options: &Options) -> i32 {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| String::from("cargo"));
+    let manifest_path = flag_values(&options.forwarded_args, &["--manifest-path"]).pop();
+    let mut metadata_command = MetadataCommand::new();
+    if let Some(manifest_path) = &manifest_path {
+        metadata_command.manifest_path(manifest_path);
+    }
+    let meta = match metadata_command.no_deps().exec() {
+        Ok(meta) => meta,
+        Err(error) => {
+            eprintln!("error: cannot read the cargo metadata: {}", error);
+            return 2;
+        }
+    };
+    // A target directory of its own, so the normal builds are not invalidated
+    let target_dir = meta.target_directory.join("rsaa");
+    let results_dir = target_dir.join("results");
+    let _ = fs::remove_dir_all(&results_dir);
+    if let Err(error) = fs::create_dir_all(&results_dir) {
+        eprintln!("error: cannot create the results directory {}: {}", results_dir, error);
+        return 2;
+    }
+
+    // Cargo does not call rustc again for up to date crates, remove them to analyze them again
+    let selected_packages = flag_values(&options.forwarded_args, &["-p", "--package"]);
+    let workspace_packages = meta.packages.iter().filter(|package| meta.workspace_members.contains(&package.id));
+    for package in workspace_packages {
+        if !selected_packages.is_empty() && !selected_packages.contains(&package.name) {
+            continue;
+        }
+        let mut clean = Command::new(&cargo);
+        clean.args(["clean", "--quiet", "--package", &package.name, "--target-dir", target_dir.as_str()]);
+        if let Some(manifest_path) = &manifest_path {
+            clean.args(["--manifest-path", manifest_path]);
+        }
+        if let Err(error) = clean.status() {
+            eprintln!("error: cannot run `{} clean`: {}", cargo, error);
+            return 2;
+        }
+    }
+
+    let wrapper = match env::current_exe() {
+        Ok(wrapper) => wrapper,
+        Err(error) => {
+            eprintln!("error: cannot find the path of cargo-rsaa: {}", error);
+            return 2;
+        }
+    };
+    let status = Command::new(&cargo)
+        .args(["check", "--target-dir", target_dir.as_str()])
+        .args(&options.forwarded_args)
+        .env("RUSTC_WORKSPACE_WRAPPER", wrapper)
+        .env(RSAA_ARGS, serde_json::to_string(&options.to_args()).unwrap())
+        .env(RSAA_RESULTS, results_dir.as_str())
+        .status();
+    let status = match status {
+        Ok(status) => status,
+        Err(error) => {
+            eprintln!("error: cannot run `{} check`: {}", cargo, error);
+            return 2;
+        }
+    };
+
+    let findings = match report_targets(results_dir.as_std_path(), options.format) {
+        Ok(findings) => findings,
+        Err(error) => {
+            eprintln!("error: cannot read the results in {}: {}", results_dir, error);
+            return 2;
+        }
+    };
+    match status.code() {
+        Some(0) if findings == 0 => 0,
+        Some(_) => 1,
+        None => 2,
+    }
 }
 
-fn compile_time_sysroot() -> Option<String> {
-    let home = option_env!("HOME");
-    let toolchain = option_env!("RUST_CHANNEL");
-    Some(match (home, toolchain) {
-        (Some(home), Some(toolchain)) => format!("{}/.rustup/toolchains/{}-x86_64-unknown-linux-gnu", home, toolchain),
-        _ => option_env!("RUST_SYSROOT")
-            .expect("To build Miri without rustup, set the `RUST_SYSROOT` env var at build time")
-            .to_owned(),
-    })
+// Prints the results of all the analyzed targets together and returns the number of findings
+fn report_targets(results_dir: &Path, format: OutputFormat) -> io::Result<usize> {
+    let mut files: Vec<PathBuf> = fs::read_dir(results_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    files.sort();
+    let targets: Vec<Value> = files.iter()
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .filter_map(|contents| serde_json::from_str(&contents).ok())
+        .collect();
+
+    let findings: usize = targets.iter().map(|target| target["findings"].as_u64().unwrap_or(0) as usize).sum();
+    match format {
+        OutputFormat::Text => eprintln!("rsaa: {} violations found in {} targets", findings, targets.len()),
+        OutputFormat::Json => {
+            let targets: Vec<Value> = targets.iter()
+                .map(|target| json!({ "target": target["target"], "functions": target["functions"] }))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&targets).unwrap());
+        }
+        OutputFormat::Dot => {
+            for target in &targets {
+                for graph in target["graphs"].as_array().into_iter().flatten() {
+                    println!("// {} {}", target["target"].as_str().unwrap_or(""), graph["function"].as_str().unwrap_or(""));
+                    println!("{}", graph["dot"].as_str().unwrap_or(""));
+                }
+            }
+        }
+    }
+    Ok(findings)
 }
 
-fn create_compiler_config() -> rustc_interface::Config {
-    let meta = MetadataCommand::new()
-        .manifest_path("./Cargo.toml")
-        .exec()
-        .unwrap();
-
-    let filename = meta.packages[0].targets[0].src_path.clone();
-    let directory = meta.workspace_root;
-    println!("{} {}", directory, filename);
-
-    // "/home/$username/.rustup/toolchains/nightly-2022-01-01-x86_64-unknown-linux-gnu"
-    let sysroot = compile_time_sysroot().expect("Cannot find sysroot");
-    println!("{}", sysroot);
-
-    let config = rustc_interface::Config {
-        // Command line options
-        opts: config::Options {
-            maybe_sysroot: Some(path::PathBuf::from(sysroot)),
-            ..config::Options::default()
-        },
-        // cfg! configuration in addition to the default ones
-        crate_cfg: FxHashSet::default(), // FxHashSet<(String, Option<String>)>
-        input: config::Input::File(PathBuf::from(filename)),
-        input_path: Some(PathBuf::from(directory)),  // Option<PathBuf>
-        output_dir: None,  // Option<PathBuf>
-        output_file: None, // Option<PathBuf>
-        file_loader: None, // Option<Box<dyn FileLoader + Send + Sync>>
-        diagnostic_output: rustc_session::DiagnosticOutput::Default,
-        stderr: None,                    // Option<Arc<Mutex<Vec<u8>>>>
-        lint_caps: FxHashMap::default(), // FxHashMap<lint::LintId, lint::Level>
-        parse_sess_created: None, //Option<Box<dyn FnOnce(&mut ParseSess) + Send>>
-        register_lints: None, // Option<Box<dyn Fn(&Session, &mut LintStore) + Send + Sync>>
-        override_queries: None, // Option<fn(&Session, &mut ty::query::Providers<'_>, &mut ty::query::Providers<'_>)>
-        registry: registry::Registry::new(&rustc_error_codes::DIAGNOSTICS),
-        make_codegen_backend: None,
+// Compiles one workspace crate with the arguments given by cargo and analyzes it
+fn run_wrapper(rustc: &str, rustc_args: &[String]) -> i32 {
+    // Version queries, build scripts and the workspace crates that are only built as dependencies
+    // of the selected packages are not analyzed
+    let is_build_script = rustc_args.windows(2).any(|pair| pair[0] == "--crate-name" && pair[1] == "build_script_build");
+    let is_selected = env::var_os("CARGO_PRIMARY_PACKAGE").is_some();
+    let options = env::var(RSAA_ARGS).ok()
+        .and_then(|args| serde_json::from_str::<Vec<String>>(&args).ok())
+        .and_then(|args| cli::parse(args).ok());
+    let options = match options {
+        Some(options) if is_selected && !is_build_script && rustc_args.iter().any(|arg| arg.ends_with(".rs")) => options,
+        _ => return run_rustc(rustc, rustc_args),
     };
+    cli::init_logger(options.verbosity);
+
+    // The rustc libraries look for the sysroot next to the executable, which is not a toolchain
+    let mut args = vec![String::from(rustc)];
+    args.extend_from_slice(rustc_args);
+    if flag_values(rustc_args, &["--sysroot"]).is_empty() {
+        match find_sysroot(rustc_args, rustc) {
+            Ok(sysroot) => args.extend([String::from("--sysroot"), sysroot.display().to_string()]),
+            Err(error) => {
+                eprintln!("error: {}", error);
+                return 2;
+            }
+        }
+    }
+
+    let mut callbacks = AnalyzerCallbacks { options, target: target_name(rustc_args), results: None };
+    let result = rustc_driver::catch_fatal_errors(|| rustc_driver::RunCompiler::new(&args, &mut callbacks).run());
+
+    if let (Some(results), Ok(results_dir)) = (&callbacks.results, env::var(RSAA_RESULTS)) {
+        let file = Path::new(&results_dir).join(format!("{}.json", target_file_name(rustc_args)));
+        if let Err(error) = fs::write(&file, results.to_string()) {
+            eprintln!("error: cannot write the results to {}: {}", file.display(), error);
+            return 2;
+        }
+    }
+    // The findings are warnings, so the compilation only fails on the errors of rustc
+    match result {
+        Ok(Ok(())) => 0,
+        _ => 1,
+    }
+}
+
+fn run_rustc(rustc: &str, rustc_args: &[String]) -> i32 {
+    match Command::new(rustc).args(rustc_args).status() {
+        Ok(status) => status.code().unwrap_or(2),
+        Err(error) => {
+            eprintln!("error: cannot run {}: {}", rustc, error);
+            2
+        }
+    }
+}
+
+// Name of the target being compiled, like `app (lib)`
+fn target_name(rustc_args: &[String]) -> String {
+    let crate_name = flag_values(rustc_args, &["--crate-name"]).pop().unwrap_or_default();
+    let kind = if rustc_args.iter().any(|arg| arg == "--test") {
+        String::from("test")
+    } else {
+        flag_values(rustc_args, &["--crate-type"]).pop().unwrap_or_else(|| String::from("bin"))
+    };
+    format!("{} ({})", crate_name, kind)
+}
+
+// Unique name for the results of this compilation, cargo gives each one its own metadata hash
+fn target_file_name(rustc_args: &[String]) -> String {
+    let crate_name = flag_values(rustc_args, &["--crate-name"]).pop().unwrap_or_default();
+    let hash = flag_values(rustc_args, &["-C"]).iter()
+        .find_map(|codegen| codegen.strip_prefix("metadata=").map(String::from))
+        .unwrap_or_default();
+    format!("{}-{}", crate_name, hash)
+}
 
-    config
+struct AnalyzerCallbacks {
+    options: Options,
+    target: String,
+    results: Option<Value>, // Results of the target, aggregated later by `cargo rsaa`
 }
 
-fn run_compiler(config: rustc_interface::Config) {
-    rustc_interface::run_compiler(config, |compiler| {
-        compiler.enter(|queries| {
-            // Analyze the program and inspect the types of definitions.
-            queries.global_ctxt().unwrap().take().enter(|tcx| {
-                analyze(tcx);
-            })
+impl rustc_driver::Callbacks for AnalyzerCallbacks {
+    fn after_analysis<'tcx>(&mut self, _compiler: &interface::Compiler, queries: &'tcx Queries<'tcx>) -> Compilation {
+        queries.global_ctxt().unwrap().peek_mut().enter(|tcx| {
+            // Libraries have no entry function, so all of their functions are analyzed
+            let mut analysis = self.options.analysis.clone();
+            if analysis.mode == AnalysisMode::EntryFunction && tcx.entry_fn(()).is_none() {
+                analysis.mode = AnalysisMode::AllFunctions;
+            }
+            let results = analyze(tcx, &analysis);
+            // Text findings are shown by cargo as compiler messages, the other formats are printed at the end
+            let findings: usize = match self.options.format {
+                OutputFormat::Text => cli::report(tcx, &results, OutputFormat::Text),
+                _ => results.iter().map(|result| result.diagnostics.len()).sum(),
+            };
+            let graphs: Vec<Value> = results.iter()
+                .map(|result| json!({ "function": result.name, "dot": cli::dot_graph(result) }))
+                .collect();
+            self.results = Some(json!({
+                "target": self.target,
+                "findings": findings,
+                "functions": cli::results_to_json(tcx, &results),
+                "graphs": graphs,
+            }));
         });
-    });
+        Compilation::Continue
+    }
 }
@@ -1,5 +1,6 @@
 #![feature(rustc_private)]
 
+extern crate rustc_driver;
 extern crate rustc_error_codes;
 extern crate rustc_errors;
 extern crate rustc_hash;
@@ -9,46 +10,79 @@ extern crate rustc_session;
 extern crate rustc_span;
 
 use rustc_errors::registry;
-use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_hash::FxHashMap;
 use rustc_session::config;
 // use rustc_span::source_map;
 use static_alias_analyzer::analyzer::analyze;
+use static_alias_analyzer::cli::{self, Options};
+use static_alias_analyzer::sysroot::find_sysroot;
 
-use std::path;
 use std::path::PathBuf;
 use std::process;
-use std::str;
+
+fn usage() -> String {
+    format!("\
+Usage: rsaa [OPTIONS] <FILE> [RUSTC OPTIONS]
+
+{}
+
+Any other option, like --edition, --cfg, -L or --extern, is passed to rustc.
+Arguments after `--` are passed to rustc as they are.
+Exits with code 1 when the analysis finds violations and 2 when it cannot run or the program does not compile.", cli::OPTIONS)
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() == 1 {
-        println!("Please input a filename. Example");
-        println!("cargo run ./examples/hello_world.rs");
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, usage());
+            process::exit(2);
+        }
+    };
+    if options.help || options.forwarded_args.is_empty() {
+        println!("{}", usage());
         return;
     }
+    cli::init_logger(options.verbosity);
 
-    let config = create_compiler_config(&args[1]);
-    run_compiler(config);
+    let config = match create_compiler_config(&options.forwarded_args) {
+        Some(config) => config,
+        None => process::exit(2),
+    };
+    match rustc_driver::catch_fatal_errors(|| run_compiler(config, &options)) {
+        Ok(Some(0)) => {}
+        Ok(Some(_)) => process::exit(1),
+        _ => process::exit(2),
+    }
 }
 
-fn create_compiler_config(filename: &str) -> rustc_interface::Config {
-    let out = process::Command::new("rustc")
-        .arg("--print=sysroot")
-        .current_dir(".")
-        .output()
-        .unwrap();
+// Builds the compiler configuration from the input file and the rustc flags
+fn create_compiler_config(rustc_args: &[String]) -> Option<rustc_interface::Config> {
+    let mut args = vec![String::from("rsaa")];
+    args.extend_from_slice(rustc_args);
+    let matches = rustc_driver::handle_options(&args)?;
+    if matches.free.len() != 1 {
+        eprintln!("error: expected exactly one input file, found {}", matches.free.len());
+        return None;
+    }
 
-    let sysroot = str::from_utf8(&out.stdout).unwrap().trim();
+    let mut opts = config::build_session_options(&matches);
+    // A sysroot given with `--sysroot` is kept as is
+    match find_sysroot(rustc_args, "rustc") {
+        Ok(sysroot) if opts.maybe_sysroot.is_none() => opts.maybe_sysroot = Some(sysroot),
+        Ok(_) => {}
+        Err(error) => {
+            eprintln!("error: {}", error);
+            return None;
+        }
+    }
 
-    let config = rustc_interface::Config {
+    Some(rustc_interface::Config {
         // Command line options
-        opts: config::Options {
-            maybe_sysroot: Some(path::PathBuf::from(sysroot)),
-            ..config::Options::default()
-        },
+        opts,
         // cfg! configuration in addition to the default ones
-        crate_cfg: FxHashSet::default(), // FxHashSet<(String, Option<String>)>
-        input: config::Input::File(PathBuf::from(filename)),
+        crate_cfg: rustc_interface::interface::parse_cfgspecs(matches.opt_strs("cfg")), // FxHashSet<(String, Option<String>)>
+        input: config::Input::File(PathBuf::from(&matches.free[0])),
         input_path: None,  // Option<PathBuf>
         output_dir: None,  // Option<PathBuf>
         output_file: None, // Option<PathBuf>
@@ -59,20 +93,23 @@ fn create_compiler_config(filename: &str) -> rustc_interface::Config {
         parse_sess_created: None, //Option<Box<dyn FnOnce(&mut ParseSess) + Send>>
         register_lints: None, // Option<Box<dyn Fn(&Session, &mut LintStore) + Send + Sync>>
         override_queries: None, // Option<fn(&Session, &mut ty::query::Providers<'_>, &mut ty::query::Providers<'_>)>
-        registry: registry::Registry::new(&rustc_error_codes::DIAGNOSTICS),
+        registry: registry::Registry::new(rustc_error_codes::DIAGNOSTICS),
         make_codegen_backend: None,
-    };
-
-    config
+    })
 }
 
-fn run_compiler(config: rustc_interface::Config) {
+// Returns the number of findings, or None if the program could not be compiled
+fn run_compiler(config: rustc_interface::Config, options: &Options) -> Option<usize> {
     rustc_interface::run_compiler(config, |compiler| {
         compiler.enter(|queries| {
             // Analyze the program and inspect the types of definitions.
-            queries.global_ctxt().unwrap().take().enter(|tcx| {
-                analyze(tcx);
+            queries.global_ctxt().ok()?.take().enter(|tcx| {
+                let results = analyze(tcx, &options.analysis);
+                // Errors of rustc itself, like the deny-by-default lints, mean the program does not compile
+                let compiled = !tcx.sess.diagnostic().has_errors_or_lint_errors();
+                let findings = cli::report(tcx, &results, options.format);
+                compiled.then(|| findings)
             })
-        });
-    });
+        })
+    })
 }
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use rustc_middle::mir::{CastKind, Rvalue, StatementKind};
+use rustc_middle::mir::terminator::TerminatorKind;
+use rustc_middle::ty::{Instance, ParamEnv, TyCtxt};
+use rustc_middle::ty::adjustment::PointerCast;
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::{Graph, NodeIndex};
+
+use crate::mir_visitor::helper::{drop_glue, is_followed_call, reified_function, resolve_call};
+
+// Functions reachable from an entry function through the calls the analysis follows,
+// resolved to the instances that run, and the drop glue of the dropped values
+pub struct CallGraph<'tcx> {
+    pub graph: Graph<Instance<'tcx>, ()>,
+    nodes: HashMap<Instance<'tcx>, NodeIndex>,
+}
+
+impl<'tcx> CallGraph<'tcx> {
+    pub fn new(tcx: TyCtxt<'tcx>, entry: Instance<'tcx>, param_env: ParamEnv<'tcx>) -> CallGraph<'tcx> {
+        let mut call_graph = CallGraph { graph: Graph::new(), nodes: HashMap::new() };
+        let mut pending = vec![call_graph.add_function(entry)];
+        while let Some(caller) = pending.pop() {
+            let instance = call_graph.graph[caller];
+            let body = tcx.instance_mir(instance.def);
+            for data in body.basic_blocks() {
+                let mut callees = match &data.terminator().kind {
+                    TerminatorKind::Call { func, .. } => resolve_call(tcx, instance, param_env, func.ty(body, tcx)).into_iter()
+                        .filter(|callee| is_followed_call(tcx, func, *callee))
+                        .collect(),
+                    TerminatorKind::Drop { place, .. } => drop_glue(tcx, instance, param_env, place.ty(body, tcx).ty).into_iter().collect(),
+                    _ => Vec::new(),
+                };
+                // A function turned into a function pointer may be called by the function from then on
+                for statement in &data.statements {
+                    let rvalue = match &statement.kind {
+                        StatementKind::Assign(assignment) => &assignment.1,
+                        _ => continue,
+                    };
+                    if let Rvalue::Cast(CastKind::Pointer(PointerCast::ReifyFnPointer), operand, _) = rvalue {
+                        let function = reified_function(tcx, instance, param_env, operand.ty(body, tcx)).map(|def_id| Instance::mono(tcx, def_id));
+                        callees.extend(function.filter(|function| is_followed_call(tcx, operand, *function)));
+                    }
+                }
+                for callee in callees {
+                    let is_new = !call_graph.nodes.contains_key(&callee);
+                    let node = call_graph.add_function(callee);
+                    call_graph.graph.update_edge(caller, node, ());
+                    if is_new {
+                        pending.push(node);
+                    }
+                }
+            }
+        }
+        call_graph
+    }
+
+    fn add_function(&mut self, instance: Instance<'tcx>) -> NodeIndex {
+        match self.nodes.get(&instance) {
+            Some(node) => *node,
+            None => {
+                let node = self.graph.add_node(instance);
+                self.nodes.insert(instance, node);
+                node
+            }
+        }
+    }
+
+    // Groups of functions that call each other (strongly connected components), callees before
+    // their callers, each with whether its functions are recursive
+    pub fn components(&self) -> Vec<(Vec<Instance<'tcx>>, bool)> {
+        tarjan_scc(&self.graph).into_iter()
+            .map(|component| {
+                let recursive = component.len() > 1 || self.graph.contains_edge(component[0], component[0]);
+                (component.into_iter().map(|node| self.graph[node]).collect(), recursive)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::Write;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use petgraph::dot::{Dot, Config};
+use rustc_middle::ty::TyCtxt;
+use serde_json::{json, Value};
+
+use crate::analyzer::{AliasBackend, AnalysisMode, AnalysisOptions, FunctionAnalysis};
+use crate::diagnostics::Checker;
+
+// Options shared by `rsaa` and `cargo rsaa`
+pub const OPTIONS: &str = "\
+Options:
+    --entry <PATH>      Analyze the function with this path, can be repeated
+    --all               Analyze every function of the crate
+    --unsafe            Analyze only the functions that use `unsafe`
+    --format <FORMAT>   Output format: text (default), json or dot
+    --checkers <LIST>   Comma separated checkers to run: stacked-borrows, mutable-arguments
+    --path-depth <N>    Fields followed inside a variable before they are merged, 4 by default
+    --points-to <NAME>  Points-to analysis for the alias queries: andersen (default) or
+                        steensgaard, a fast mode that also visits each block only once
+    -v, --verbose       Print the progress of the analysis, repeat for more detail
+    -q, --quiet         Do not print warnings of the analyzer itself
+    -h, --help          Print this message";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text, // Compiler diagnostics
+    Json, // One object per analyzed function, with its diagnostics
+    Dot, // Points-to graph of each analyzed function
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub analysis: AnalysisOptions,
+    pub format: OutputFormat,
+    pub verbosity: LevelFilter,
+    pub forwarded_args: Vec<String>, // Arguments for rustc in `rsaa` and for `cargo check` in `cargo rsaa`
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            analysis: AnalysisOptions::default(),
+            format: OutputFormat::Text,
+            verbosity: LevelFilter::Warn,
+            forwarded_args: Vec::new(),
+            help: false,
+        }
+    }
+}
+
+// Flags of the analyzer that take a value, given as `--flag value` or `--flag=value`
+const VALUE_FLAGS: [&str; 5] = ["--entry", "--format", "--checkers", "--path-depth", "--points-to"];
+
+// Parses the arguments that follow the binary name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut entries: Vec<String> = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(mut arg) = args.next() {
+        let mut inline_value = None;
+        if let Some((flag, value)) = arg.split_once('=').filter(|(flag, _)| VALUE_FLAGS.contains(flag)) {
+            inline_value = Some(String::from(value));
+            arg = String::from(flag);
+        }
+        let mut next_value = |flag: &str| inline_value.take().map_or_else(|| value(&mut args, flag), Ok);
+        match arg.as_str() {
+            "--entry" => entries.push(next_value(&arg)?),
+            "--all" => options.analysis.mode = AnalysisMode::AllFunctions,
+            "--unsafe" => options.analysis.mode = AnalysisMode::UnsafeFunctions,
+            "--format" => {
+                options.format = match next_value(&arg)?.as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    "dot" => OutputFormat::Dot,
+                    other => return Err(format!("unknown output format `{}`", other)),
+                }
+            }
+            "--checkers" => {
+                options.analysis.checkers = next_value(&arg)?.split(',')
+                    .map(|name| Checker::from_name(name).ok_or(format!("unknown checker `{}`", name)))
+                    .collect::<Result<Vec<Checker>, String>>()?;
+            }
+            "--path-depth" => {
+                let depth = next_value(&arg)?;
+                options.analysis.path_depth = depth.parse().map_err(|_| format!("invalid path depth `{}`", depth))?;
+            }
+            "--points-to" => {
+                let name = next_value(&arg)?;
+                options.analysis.alias_backend = AliasBackend::from_name(&name).ok_or(format!("unknown points-to analysis `{}`", name))?;
+            }
+            "-v" | "--verbose" => options.verbosity = more_verbose(options.verbosity),
+            "-q" | "--quiet" => options.verbosity = LevelFilter::Off,
+            "-h" | "--help" => options.help = true,
+            "--" => options.forwarded_args.extend(&mut args),
+            _ => options.forwarded_args.push(arg),
+        }
+    }
+
+    if !entries.is_empty() {
+        options.analysis.mode = AnalysisMode::Functions(entries);
+    }
+    Ok(options)
+}
+
+impl Options {
+    // Arguments that `parse` turns back into these options, without the forwarded ones
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        match &self.analysis.mode {
+            AnalysisMode::EntryFunction => {}
+            AnalysisMode::AllFunctions => args.push(String::from("--all")),
+            AnalysisMode::UnsafeFunctions => args.push(String::from("--unsafe")),
+            AnalysisMode::Functions(paths) => {
+                for path in paths {
+                    args.extend([String::from("--entry"), path.clone()]);
+                }
+            }
+        }
+        let format = match self.format {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Dot => "dot",
+        };
+        args.extend([String::from("--format"), String::from(format)]);
+        let checkers: Vec<&str> = self.analysis.checkers.iter().map(|checker| checker.name()).collect();
+        args.extend([String::from("--checkers"), checkers.join(",")]);
+        args.extend([String::from("--path-depth"), self.analysis.path_depth.to_string()]);
+        args.extend([String::from("--points-to"), String::from(self.analysis.alias_backend.name())]);
+        match self.verbosity {
+            LevelFilter::Off => args.push(String::from("--quiet")),
+            LevelFilter::Error | LevelFilter::Warn => {}
+            LevelFilter::Info => args.push(String::from("-v")),
+            LevelFilter::Debug => args.extend([String::from("-v"), String::from("-v")]),
+            LevelFilter::Trace => args.extend([String::from("-v"), String::from("-v"), String::from("-v")]),
+        }
+        args
+    }
+}
+
+// Values of a flag given as `--flag value` or `--flag=value`, and of a short flag
+// also as `-fvalue`, like cargo's `-pNAME`
+pub fn flag_values(args: &[String], names: &[&str]) -> Vec<String> {
+    let mut values = Vec::new();
+    for (index, arg) in args.iter().enumerate() {
+        for name in names {
+            let is_short = name.len() == 2 && !name.starts_with("--");
+            if arg == name {
+                values.extend(args.get(index + 1).cloned());
+            } else if let Some(value) = arg.strip_prefix(&format!("{}=", name)) {
+                values.push(String::from(value));
+            } else if let Some(value) = arg.strip_prefix(name).filter(|_| is_short) {
+                values.push(String::from(value));
+            }
+        }
+    }
+    values
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or(format!("missing value for `{}`", flag))
+}
+
+fn more_verbose(level: LevelFilter) -> LevelFilter {
+    match level {
+        LevelFilter::Off | LevelFilter::Error => LevelFilter::Warn,
+        LevelFilter::Warn => LevelFilter::Info,
+        LevelFilter::Info => LevelFilter::Debug,
+        LevelFilter::Debug | LevelFilter::Trace => LevelFilter::Trace,
+    }
+}
+
+// Prints the messages of the analyzer to stderr, stdout is left for the results
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+pub fn init_logger(verbosity: LevelFilter) {
+    if log::set_logger(&StderrLogger).is_ok() {
+        log::set_max_level(verbosity);
+    }
+}
+
+// Prints the results in the selected format and returns the number of findings
+pub fn report(tcx: TyCtxt, results: &[FunctionAnalysis], format: OutputFormat) -> usize {
+    match format {
+        OutputFormat::Text => {
+            for diagnostic in results.iter().flat_map(|result| &result.diagnostics) {
+                diagnostic.emit(tcx);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results_to_json(tcx, results)).unwrap()),
+        OutputFormat::Dot => {
+            for result in results {
+                println!("// {}", result.name);
+                println!("{}", dot_graph(result));
+            }
+        }
+    }
+    results.iter().map(|result| result.diagnostics.len()).sum()
+}
+
+// One object per function, with its diagnostics
+pub fn results_to_json(tcx: TyCtxt, results: &[FunctionAnalysis]) -> Value {
+    results.iter().map(|result| json!({
+        "function": result.name,
+        "diagnostics": result.diagnostics.iter().map(|diagnostic| diagnostic.to_json(tcx)).collect::<Vec<_>>(),
+    })).collect()
+}
+
+pub fn dot_graph(result: &FunctionAnalysis) -> String {
+    format!("{:?}", Dot::with_config(&result.alias_graph.graph, &[Config::EdgeNoLabel]))
+}
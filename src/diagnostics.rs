@@ -0,0 +1,208 @@
+use std::fmt;
+
+use rustc_hir::def_id::DefId;
+use rustc_middle::mir::{Body, Location};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+use serde_json::{json, Value};
+
+use crate::points_to::{Allocation, PlacePath};
+use crate::stacked_borrows::{AccessKind, Invalidation, Tag, Violation};
+use crate::utils::{local_name, upvar_name};
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    InvalidReadAccess, // Read through a tag that is not in the borrow stack
+    InvalidWriteAccess, // Write through a tag that is not in the borrow stack
+    ProtectedTagPopped, // Access during a call that removed the tag of a reference argument from the borrow stack
+    AliasingMutableArguments, // Function called with two mutable arguments that alias, an error if they always do
+}
+
+impl DiagnosticKind {
+    pub fn checker(&self) -> Checker {
+        match self {
+            DiagnosticKind::InvalidReadAccess | DiagnosticKind::InvalidWriteAccess | DiagnosticKind::ProtectedTagPopped => Checker::StackedBorrows,
+            DiagnosticKind::AliasingMutableArguments => Checker::MutableArguments,
+        }
+    }
+}
+
+// Group of findings that can be enabled from the command line
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Checker {
+    StackedBorrows, // Accesses through tags that were popped from the borrow stacks
+    MutableArguments, // Calls with mutable arguments that may alias
+}
+
+impl Checker {
+    pub const ALL: [Checker; 2] = [Checker::StackedBorrows, Checker::MutableArguments];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Checker::StackedBorrows => "stacked-borrows",
+            Checker::MutableArguments => "mutable-arguments",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Checker> {
+        match name {
+            "stacked-borrows" => Some(Checker::StackedBorrows),
+            "mutable-arguments" => Some(Checker::MutableArguments),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+// A finding of the analysis
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub severity: Severity,
+    pub span: Span,
+    pub location: Location,
+    pub function: DefId,
+    pub allocation: Option<PlacePath>,
+    pub allocation_name: Option<String>,
+    pub tags: Vec<Tag>,
+    pub invalidated_by: Option<Invalidation>,
+    pub creation_span: Option<Span>, // Where the tag of the access was created
+    pub invalidation_span: Option<Span>, // Where the tag of the access was removed from the stack
+}
+
+impl Diagnostic {
+    // True if both findings come from the same access: the same checker at the same code with
+    // the same tags, like the read and the write of `*x += 1` or the accesses to a place and its fields.
+    // A tag that was never created, because the pointer it was derived from was already invalid,
+    // is a consequence of the other finding.
+    pub fn repeats(&self, other: &Diagnostic) -> bool {
+        let never_created = self.kind.checker() == Checker::StackedBorrows && self.creation_span.is_none() && self.invalidated_by.is_none();
+        self.kind.checker() == other.kind.checker() && self.function == other.function && self.span == other.span
+            && (self.tags == other.tags || never_created)
+    }
+
+    pub fn from_violation(violation: Violation, path: PlacePath, body: &Body, location: Location) -> Diagnostic {
+        let kind = match violation.access {
+            _ if violation.protected => DiagnosticKind::ProtectedTagPopped,
+            AccessKind::Read => DiagnosticKind::InvalidReadAccess,
+            _ => DiagnosticKind::InvalidWriteAccess,
+        };
+        Diagnostic {
+            kind,
+            severity: Severity::Error,
+            span: body.source_info(location).span,
+            location,
+            function: body.source.def_id(),
+            allocation_name: match path.allocation {
+                Allocation::Local(local) => local_name(body, local).map(|name| name + &path.projections_suffix()),
+                // Memory behind a parameter, named after the pointer like `*x` or `(*x).0`
+                Allocation::Unknown(local, derefs) => local_name(body, local).map(|name| {
+                    let pointee = "*".repeat(derefs as usize) + &name;
+                    if path.projections.is_empty() { pointee } else { format!("({}){}", pointee, path.projections_suffix()) }
+                }),
+                // Memory behind a captured variable, named after the variable
+                Allocation::Upvar(local, field, derefs) => upvar_name(body, local, field).and_then(|(name, captured)| {
+                    let stars = (derefs as usize).checked_sub(captured)?;
+                    let pointee = "*".repeat(stars) + &name;
+                    Some(if stars == 0 || path.projections.is_empty() { pointee + &path.projections_suffix() } else { format!("({}){}", pointee, path.projections_suffix()) })
+                }),
+                _ => None,
+            },
+            allocation: Some(path),
+            tags: vec![violation.tag],
+            invalidated_by: violation.invalidated_by,
+            creation_span: violation.created_at.map(|created_at| body.source_info(created_at).span),
+            invalidation_span: violation.invalidated_by.map(|invalidation| body.source_info(invalidation.location).span),
+        }
+    }
+
+    // Emits the finding through the compiler as a warning, so it does not fail the build.
+    // The severity is shown in the label of the span.
+    pub fn emit(&self, tcx: TyCtxt) {
+        let mut builder = tcx.sess.struct_span_warn(self.span, &self.message());
+        builder.span_label(self.span, format!("{} found by rsaa", self.severity));
+        if let Some(span) = self.creation_span {
+            builder.span_note(span, &format!("tag {:?} was created here", self.tags[0]));
+        }
+        if let (Some(span), Some(invalidation)) = (self.invalidation_span, self.invalidated_by) {
+            builder.span_note(span, &format!("tag {:?} was invalidated here, by a {} with tag {:?}",
+                self.tags[0], invalidation.access, invalidation.tag));
+        }
+        if self.tags[0] == Tag::Wildcard {
+            builder.note("the pointer was cast from an integer, and none of the pointers exposed by casting them to integers can access this place");
+        }
+        builder.emit();
+    }
+
+    pub fn to_json(&self, tcx: TyCtxt) -> Value {
+        json!({
+            "kind": format!("{:?}", self.kind),
+            "severity": self.severity.to_string(),
+            "message": self.message(),
+            "function": tcx.def_path_str(self.function),
+            "span": span_to_json(tcx, self.span),
+            "created_at": self.creation_span.map(|span| span_to_json(tcx, span)),
+            "invalidated_at": self.invalidation_span.map(|span| span_to_json(tcx, span)),
+        })
+    }
+
+    pub fn message(&self) -> String {
+        let allocation = match (&self.allocation_name, &self.allocation) {
+            (Some(name), _) => format!("`{}`", name),
+            (None, Some(allocation)) => format!("{:?}", allocation),
+            (None, None) => String::from("memory"),
+        };
+        match self.kind {
+            DiagnosticKind::InvalidReadAccess =>
+                format!("tag {:?} does not have read access to {}", self.tags[0], allocation),
+            DiagnosticKind::InvalidWriteAccess =>
+                format!("tag {:?} does not have write access to {}", self.tags[0], allocation),
+            DiagnosticKind::ProtectedTagPopped =>
+                format!("protected tag {:?} was popped from {} during the call", self.tags[0], allocation),
+            DiagnosticKind::AliasingMutableArguments => {
+                let alias = match self.severity {
+                    Severity::Error => "alias",
+                    Severity::Warning => "may alias",
+                };
+                format!("function called with mutable arguments {:?} and {:?} that {}", self.tags[0], self.tags[1], alias)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} at {:?} ({:?} in {:?})", self.severity, self.message(), self.span, self.location, self.function)?;
+        if let Some(invalidation) = self.invalidated_by {
+            write!(f, "\n  tag {:?} was invalidated by a {} with tag {:?} at {:?}",
+                self.tags[0], invalidation.access, invalidation.tag, invalidation.location)?;
+        }
+        Ok(())
+    }
+}
+
+fn span_to_json(tcx: TyCtxt, span: Span) -> Value {
+    let source_map = tcx.sess.source_map();
+    let (start, end) = (source_map.lookup_char_pos(span.lo()), source_map.lookup_char_pos(span.hi()));
+    json!({
+        "file": start.file.name.prefer_local().to_string(),
+        "line": start.line,
+        "column": start.col.0 + 1,
+        "end_line": end.line,
+        "end_column": end.col.0 + 1,
+    })
+}
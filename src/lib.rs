@@ -8,6 +8,7 @@ extern crate rustc_driver;
 extern crate rustc_errors;
 extern crate rustc_hir;
 extern crate rustc_hir_pretty;
+extern crate rustc_index;
 extern crate rustc_interface;
 extern crate rustc_middle;
 extern crate rustc_span;
@@ -15,13 +16,21 @@ extern crate rustc_target;
 extern crate log;
 
 pub mod analyzer;
+pub mod call_graph;
 pub mod utils;
 pub mod stacked_borrows;
 pub mod points_to;
+pub mod andersen;
+pub mod steensgaard;
+pub mod summaries;
+pub mod diagnostics;
+pub mod cli;
+pub mod sysroot;
 
 pub mod mir_visitor {
     pub mod block_visitor;
     pub mod body_visitor;
+    pub mod dataflow;
     pub mod terminator_visitor;
     pub mod helper;
 }
\ No newline at end of file
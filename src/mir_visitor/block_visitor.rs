@@ -3,12 +3,17 @@ use rustc_middle::mir::{Place, Rvalue};
 use rustc_middle::mir::StatementKind::{Assign, SetDiscriminant, StorageDead, StorageLive};
 use rustc_middle::mir::Operand;
 use rustc_middle::mir::Rvalue::{*};
-use rustc_middle::mir::BorrowKind;
+use rustc_middle::mir::{AggregateKind, BorrowKind, CastKind};
+use rustc_middle::ty::adjustment::PointerCast;
 use rustc_middle::mir::ConstantKind;
 use rustc_target::abi::VariantIdx;
+use log::debug;
 
 use crate::stacked_borrows::{*};
+use crate::points_to::{Allocation, PathElem, PlacePath};
+use crate::points_to::{Constraint, PlaceExpr};
 use super::body_visitor::MirVisitor;
+use super::helper::reified_function;
 
 // Visitor trait implementation
 impl<'tcx> MirVisitor<'tcx> {
@@ -17,7 +22,7 @@ impl<'tcx> MirVisitor<'tcx> {
         block: BasicBlock,
         data: &BasicBlockData<'tcx>
     ) {
-        println!("Block {:#?} --Start", block);
+        debug!("Block {:#?} --Start", block);
         let mut location = block.start_location();
         // Visit each statement of the basic block
         for statement in &data.statements {
@@ -29,7 +34,7 @@ impl<'tcx> MirVisitor<'tcx> {
         if let Some(terminator) = &data.terminator {
             self.visit_terminator(terminator, location);
         }
-        println!("Block {:#?} --End \n", block);
+        debug!("Block {:#?} --End \n", block);
     }
 
     fn visit_statement(
@@ -45,10 +50,10 @@ impl<'tcx> MirVisitor<'tcx> {
             SetDiscriminant {
                 place,
                 variant_index,
-            } => self.visit_set_discriminant(place, *variant_index),
+            } => self.visit_set_discriminant(place, *variant_index, location),
             StorageDead(local) | StorageLive(local) => self.visit_storage(*local),
 
-            other => println!("Statement Kind not recognized {:?}", other)
+            other => debug!("Statement Kind not recognized {:?}", other)
         }
     }
 
@@ -61,8 +66,9 @@ impl<'tcx> MirVisitor<'tcx> {
         &mut self,
         place: &Place<'tcx>,
         variant_index: VariantIdx,
+        location: Location
     ) {
-        self.add_to_stack(place, self.place_to_tag(place));
+        self.add_to_stack(place, self.place_to_tag(place), location);
     }
 
     fn visit_assign(
@@ -71,95 +77,232 @@ impl<'tcx> MirVisitor<'tcx> {
         rvalue: &Rvalue<'tcx>,
         location: Location
     ) {
-        let variable = place.local.as_u32();
         let tag = self.place_to_tag(place);
 
         match rvalue {
             // Create or mutate variable (x or *x)
             Use(operand) => {
-                print!("use ");
                 self.visit_operand(operand, location);
-                self.add_to_stack(place, tag);
-                if !place.is_indirect() { // is not a (*x)
-                    self.alias_graph.constant(variable);
-                }
-                if let Operand::Move(_) = operand {
-                    self.alias_graph.points_to(variable, self.operand_as_u32(operand));
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
+                match operand {
+                    Operand::Move(operand_place) | Operand::Copy(operand_place) => {
+                        let operand_ty = operand_place.ty(self.body, self.tcx).ty;
+                        let pointer_permission = self.pointer_permission(operand_ty);
+                        self.copy_pointers(place, operand_place);
+                        self.add_constraint(Constraint::assign(self.place_expr(place), self.place_expr(operand_place)));
+                        // Copying a reference reborrows whatever it points to, a raw pointer is copied as it is
+                        if let Some(Permission::SharedReadWrite) = pointer_permission {
+                            let paths = self.pointee_paths(operand_place);
+                            self.copy_raw_pointer(paths, self.place_to_tag(operand_place), tag, location);
+                        } else if let Some(permission) = pointer_permission {
+                            let paths = self.pointee_paths(operand_place);
+                            self.reborrow(paths, operand_ty, self.place_to_tag(operand_place), tag, permission, location);
+                        } else if !operand_ty.is_integral() {
+                            // Copying a value with pointers in its fields, like a tuple of references
+                            let mut paths: Vec<PlacePath> = self.place_paths(operand_place).iter()
+                                .flat_map(|path| self.alias_graph.contained_targets(path))
+                                .collect();
+                            paths.sort();
+                            paths.dedup();
+                            self.derive_pointer(paths, self.place_to_tag(operand_place), tag, Permission::SharedReadWrite, location);
+                        }
+                    }
+                    Operand::Constant(constant) => {
+                        // Pointer to a static, derived from the untagged pointer of the static itself
+                        if let Some(def_id) = constant.check_static_ptr(self.tcx) {
+                            let permission = self.pointer_permission(constant.ty()).unwrap_or(Permission::SharedReadWrite);
+                            let path = PlacePath::new(Allocation::Static(def_id));
+                            let stack = self.stacked_borrows.get_mut(&path);
+                            if stack.is_empty() {
+                                stack.new_ref(Tag::Untagged, Permission::SharedReadWrite, location);
+                            }
+                            self.derive_pointer(vec![path.clone()], Tag::Untagged, tag, permission, location);
+                            for dest in self.place_paths(place) {
+                                self.alias_graph.points_to(&dest, &path);
+                            }
+                            self.add_constraint(Constraint::AddressOf { dest: self.place_expr(place), target: PlaceExpr::new(path) });
+                        }
+                    }
                 }
             },
             // Reference (&x or &mut x)
-            Ref(_region, borrow_kind, place) => {
-                print!("ref ");
+            Ref(_region, borrow_kind, borrowed_place) => {
+                self.add_to_stack(place, tag, location);
+                let (paths, parent) = (self.place_paths(borrowed_place), self.place_to_tag(borrowed_place));
                 match borrow_kind {
-                    BorrowKind::Shared => { // Inmutable reference
-                        self.stacked_borrows.read_value(self.place_to_tag(place));
-                        self.stacked_borrows.new_ref(tag, Permission::SharedReadOnly);
+                    // Inmutable reference
+                    BorrowKind::Shared => {
+                        let borrowed_ty = self.monomorphize(borrowed_place.ty(self.body, self.tcx).ty);
+                        self.derive_shared(paths, borrowed_ty, parent, tag, location);
                     }
-                    _ => {  // Mutable reference
-                        self.stacked_borrows.use_value(self.place_to_tag(place));
-                        self.stacked_borrows.new_ref(tag, Permission::Unique);
-                    }
-                };
-                self.alias_graph.points_to(variable, place.local.as_u32());
+                    // Reserved until its first write
+                    BorrowKind::Mut { allow_two_phase_borrow: true } => self.derive_reserved(paths, parent, tag, location),
+                    // Mutable reference
+                    _ => self.derive_pointer(paths, parent, tag, Permission::Unique, location),
+                }
+                self.assign_constant(place);
+                self.add_borrow(place, borrowed_place);
+                self.add_constraint(Constraint::AddressOf { dest: self.place_expr(place), target: self.place_expr(borrowed_place) });
             },
             // Create a raw pointer (&raw const x)
-            AddressOf(_mutability, place) => {
-                print!("raw ");
-                self.stacked_borrows.use_value(self.place_to_tag(place));
-                self.stacked_borrows.new_ref(tag, Permission::SharedReadWrite);
-                self.alias_graph.points_to(variable, place.local.as_u32());
+            AddressOf(_mutability, borrowed_place) => {
+                self.add_to_stack(place, tag, location);
+                self.derive_pointer(self.place_paths(borrowed_place), self.place_to_tag(borrowed_place), tag, Permission::SharedReadWrite, location);
+                self.assign_constant(place);
+                self.add_borrow(place, borrowed_place);
+                self.add_constraint(Constraint::AddressOf { dest: self.place_expr(place), target: self.place_expr(borrowed_place) });
             }
             // Creates an aggregate value, like a tuple or struct
-            Aggregate(_kind,operands) => {
-                print!("agg ");
+            Aggregate(kind, operands) => {
                 for operand in operands {
                     self.visit_operand(operand, location);
                 }
-                self.add_to_stack(place, tag);
-                self.alias_graph.constant(variable);
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
+                // The pointers stored in the fields point to the same places as the operands
+                for (index, operand) in operands.iter().enumerate() {
+                    if let Operand::Move(operand_place) | Operand::Copy(operand_place) = operand {
+                        let elems = match **kind {
+                            AggregateKind::Array(_) => vec![PathElem::Index],
+                            AggregateKind::Adt(_, _, _, _, Some(active_field)) => vec![PathElem::Field(active_field as u32)],
+                            AggregateKind::Adt(def_id, variant, ..) if self.tcx.adt_def(def_id).is_enum() => {
+                                vec![PathElem::Variant(variant.as_u32()), PathElem::Field(index as u32)]
+                            }
+                            _ => vec![PathElem::Field(index as u32)],
+                        };
+                        let dest = elems.iter().fold(self.place_expr(place), |dest, elem| dest.project(*elem));
+                        self.add_constraint(Constraint::assign(dest, self.place_expr(operand_place)));
+
+                        let sources = self.place_paths(operand_place);
+                        for dest in self.place_paths(place) {
+                            let dest = elems.iter().fold(dest, |dest, elem| {
+                                if dest.projections.len() < self.options.path_depth { dest.project(*elem) } else { dest }
+                            });
+                            for source in &sources {
+                                self.alias_graph.copy(&dest, source, self.options.path_depth);
+                            }
+                        }
+                        let operand_ty = operand_place.ty(self.body, self.tcx).ty;
+                        if let Some(permission) = self.pointer_permission(operand_ty) {
+                            let paths = self.pointee_paths(operand_place);
+                            self.reborrow(paths, operand_ty, self.place_to_tag(operand_place), tag, permission, location);
+                        }
+                    }
+                }
             },
-            Cast(_cast_kind, operand, _ty) => {
-                print!("kst ");
+            Cast(cast_kind, operand, ty) => {
                 self.visit_operand(operand, location);
-                self.add_to_stack(place, tag);
-                self.alias_graph.constant(variable);
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
+                // A function pointer points to the code of the function, calls through it are resolved with it
+                if let CastKind::Pointer(PointerCast::ReifyFnPointer) = cast_kind {
+                    let func_ty = operand.ty(self.body, self.tcx);
+                    if let Some(def_id) = reified_function(self.tcx, self.instance, self.param_env, func_ty) {
+                        let function = PlacePath::new(Allocation::Function(def_id));
+                        for dest in self.place_paths(place) {
+                            self.alias_graph.points_to(&dest, &function);
+                        }
+                        self.add_constraint(Constraint::AddressOf { dest: self.place_expr(place), target: PlaceExpr::new(function) });
+                    }
+                }
+                if let Operand::Move(operand_place) | Operand::Copy(operand_place) = operand {
+                    let operand_ty = operand_place.ty(self.body, self.tcx).ty;
+                    match (self.pointer_permission(operand_ty), self.pointer_permission(ty)) {
+                        // Pointer to pointer casts keep pointing to the same places
+                        (Some(operand_permission), Some(permission)) => {
+                            self.copy_pointers(place, operand_place);
+                            self.add_constraint(Constraint::assign(self.place_expr(place), self.place_expr(operand_place)));
+                            let paths = self.pointee_paths(operand_place);
+                            if (operand_permission, permission) == (Permission::SharedReadWrite, Permission::SharedReadWrite) {
+                                self.copy_raw_pointer(paths, self.place_to_tag(operand_place), tag, location);
+                            } else {
+                                self.reborrow(paths, ty, self.place_to_tag(operand_place), tag, permission, location);
+                            }
+                        }
+                        // The address of a pointer still points to its places, and exposes its tag to
+                        // the pointers cast back from it
+                        (Some(_), None) if ty.is_integral() => {
+                            self.copy_pointers(place, operand_place);
+                            self.add_constraint(Constraint::assign(self.place_expr(place), self.place_expr(operand_place)));
+                            self.expose(self.pointee_paths(operand_place), self.place_to_tag(operand_place));
+                        }
+                        // A pointer cast from an address may have the tag of any exposed pointer
+                        (None, Some(_)) if operand_ty.is_integral() => {
+                            self.copy_pointers(place, operand_place);
+                            self.add_constraint(Constraint::assign(self.place_expr(place), self.place_expr(operand_place)));
+                            let paths = self.place_paths(operand_place).iter()
+                                .flat_map(|path| self.alias_graph.known_targets(path))
+                                .collect();
+                            self.copy_raw_pointer(paths, Tag::Wildcard, tag, location);
+                        }
+                        _ => {}
+                    }
+                }
             },
             BinaryOp(_op, box_tuple) | CheckedBinaryOp(_op, box_tuple) => {
-                print!("bin ");
                 let (operand1, operand2) = *box_tuple.clone();
                 self.visit_operand(&operand1, location);
                 self.visit_operand(&operand2, location);
-                self.add_to_stack(place, tag);
-                self.alias_graph.constant(variable);
-
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
+                // Arithmetic on an address, like aligning it, keeps pointing to the same places
+                for operand in [&operand1, &operand2] {
+                    if let Operand::Move(operand_place) | Operand::Copy(operand_place) = operand {
+                        if operand_place.ty(self.body, self.tcx).ty.is_integral() {
+                            self.copy_pointers(place, operand_place);
+                            self.add_constraint(Constraint::assign(self.place_expr(place), self.place_expr(operand_place)));
+                        }
+                    }
+                }
             },
             UnaryOp(unary, operand) => {
-                print!("un  ");
-                self.visit_operand(&operand, location);
-                self.add_to_stack(place, tag);
-                self.alias_graph.constant(variable);
+                self.visit_operand(operand, location);
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
             },
             // SizeOf(T) - AlignOf(T)
             NullaryOp(_null_op, _operand) => {
-                print!("nul ");
-                self.add_to_stack(place, tag);
-                self.alias_graph.constant(variable);
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
             },
-            ShallowInitBox(operand, _ty) => {
-                print!("box ");
-                self.add_to_stack(place, tag);
-                self.alias_graph.points_to(variable, self.operand_as_u32(operand));
+            // Array with copies of a value, like `[0u8; 64]`
+            Repeat(operand, _count) => {
+                self.visit_operand(operand, location);
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
             },
+            // The box takes ownership of a new heap allocation
+            ShallowInitBox(operand, _ty) => self.allocate_heap(place, tag, location),
             Discriminant(_place) => {
-                print!("dsc ");
-                self.add_to_stack(place, tag);
-                self.alias_graph.constant(variable);
+                self.add_to_stack(place, tag, location);
+                self.assign_constant(place);
 
             }
-            other => println!("Rvalue kind not recognized {:?} ", other),
+            other => debug!("Rvalue kind not recognized {:?} ", other),
         }
 
-        println!("{:#?} Assign {:?} = {:?} | {:#?}", location, place, rvalue, self.stacked_borrows);
+        debug!("{:#?} Assign {:?} = {:?} | {:?}", location, place, rvalue, self.stacked_borrows);
+    }
+
+    // Every place the destination may be points to every place the borrowed place may be
+    fn add_borrow(&mut self, place: &Place<'tcx>, borrowed_place: &Place<'tcx>) {
+        let targets = self.place_paths(borrowed_place);
+        for dest in self.place_paths(place) {
+            for target in &targets {
+                self.alias_graph.points_to(&dest, target);
+            }
+        }
+    }
+
+    // Every place the destination may be gets the pointers stored in the places the source may be
+    fn copy_pointers(&mut self, place: &Place<'tcx>, source: &Place<'tcx>) {
+        let sources = self.place_paths(source);
+        for dest in self.place_paths(place) {
+            for source in &sources {
+                self.alias_graph.copy(&dest, source, self.options.path_depth);
+            }
+        }
     }
 
     pub fn visit_operand(
@@ -170,7 +313,7 @@ impl<'tcx> MirVisitor<'tcx> {
         match operand {
             Operand::Move(place) | Operand::Copy(place) => {
                 if !place.projection.is_empty() {
-                    self.stacked_borrows.use_value(self.place_to_tag(place));
+                    self.read_place(place, self.place_to_tag(place), location);
                 }
             }
             Operand::Constant(boxed_constant) => {
@@ -1,29 +1,68 @@
-use rustc_middle::mir::{Local, LocalDecl, LocalDecls, Body};
-use rustc_middle::mir::Operand;
-use rustc_middle::ty::{TyCtxt};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use rustc_index::vec::IndexVec;
+use rustc_middle::mir::{BasicBlock, Local, LocalDecl, LocalDecls, Body, START_BLOCK};
+use rustc_middle::ty::{Instance, ParamEnv, TyCtxt};
+use log::debug;
 
 use crate::stacked_borrows::{*};
-use crate::points_to::PointsToGraph;
+use crate::points_to::{AliasAnalysis, PlacePath, PointsToGraph};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::analyzer::AnalysisOptions;
+use crate::summaries::{FunctionSummary, SummaryCache};
+use super::helper::raw_pointer_provenance;
 
 pub struct MirVisitor<'tcx> {
     pub tcx: TyCtxt<'tcx>,
+    pub instance: Instance<'tcx>, // Function analyzed, with the generic arguments it is called with
+    pub param_env: ParamEnv<'tcx>, // Of the function the analysis started from, to resolve calls
     pub body: &'tcx Body<'tcx>,
-    pub args: Vec<Operand<'tcx>>,
     pub local_declarations: LocalDecls<'tcx>,
-    pub stacked_borrows: Stack,
+    pub stacked_borrows: BorrowStacks,
     pub alias_graph: PointsToGraph,
+    pub entry_graphs: IndexVec<BasicBlock, Option<PointsToGraph>>, // Points-to graph at the start of each basic block
+    pub points_to: Box<dyn AliasAnalysis>, // Points-to sets of the whole function, for the alias queries
+    pub argument_checks: Vec<(Diagnostic, PlacePath, PlacePath)>, // Reported if the arguments may alias
+    pub diagnostics: Vec<Diagnostic>,
+    pub accesses: BTreeMap<PlacePath, AccessKind>, // Accesses to the memory behind the parameters
+    pub exposed: BTreeSet<PlacePath>, // Memory behind the parameters whose address was cast to an integer
+    pub provenance: HashMap<u32, Tag>, // Tags of the raw pointers derived from other pointers or integers
+    pub summaries: SummaryCache<'tcx>, // Shared with the callees, moved in and out of their visitors
+    pub options: AnalysisOptions,
 }
 
 // Basic Functions
 impl<'tcx> MirVisitor<'tcx> {
-    pub fn new(tcx: TyCtxt<'tcx>, body:&'tcx Body<'tcx>, args: Vec<Operand<'tcx>>) -> Self {
+    pub fn new(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>, param_env: ParamEnv<'tcx>, options: AnalysisOptions) -> Self {
+        let body = tcx.instance_mir(instance.def);
         MirVisitor {
             tcx,
+            instance,
+            param_env,
             body,
-            args,
             local_declarations: LocalDecls::new(),
-            stacked_borrows: Stack::new(),
-            alias_graph: PointsToGraph::new()
+            stacked_borrows: BorrowStacks::new(),
+            alias_graph: PointsToGraph::new(),
+            entry_graphs: IndexVec::new(),
+            points_to: options.alias_backend.new_analysis(options.path_depth),
+            argument_checks: Vec::new(),
+            diagnostics: Vec::new(),
+            accesses: BTreeMap::new(),
+            exposed: BTreeSet::new(),
+            provenance: raw_pointer_provenance(tcx, body),
+            summaries: SummaryCache::new(),
+            options,
+        }
+    }
+
+    // Effect of the function on its callers, once the body has been visited
+    pub fn summary(&self) -> FunctionSummary {
+        FunctionSummary {
+            alias_graph: self.alias_graph.clone(),
+            accesses: self.accesses.clone(),
+            diagnostics: self.diagnostics.clone(),
+            exposed: self.exposed.clone(),
+            wildcard_return: self.local_tag(0) == Tag::Wildcard,
         }
     }
 }
@@ -31,7 +70,7 @@ impl<'tcx> MirVisitor<'tcx> {
 // Visitor trait implementation
 impl<'tcx> MirVisitor<'tcx> {
     pub fn visit_body(&mut self, body: &Body<'tcx>) {
-        println!("Main body -- Start");
+        debug!("Main body -- Start");
         // Visit local declarations
         let local_declarations = body.local_decls.clone();
         for (local, local_decl) in local_declarations.into_iter_enumerated() {
@@ -41,14 +80,23 @@ impl<'tcx> MirVisitor<'tcx> {
         // Visit arguments and local declarations
         self.push_args();
         self.local_declarations = body.local_decls.clone();
-        println!("\n");
 
-        // Visit function basic blocks
-        let basic_blocks = body.basic_blocks().clone();
-        for (block, data) in basic_blocks.into_iter_enumerated() {
-            self.visit_basic_block_data(block, &data);
+        // Visit function basic blocks following the control flow until a fixpoint is reached
+        self.visit_blocks_to_fixpoint(body);
+
+        // The points-to sets are known once every statement has been visited.
+        // The calls made inside other crates, like the standard library, are not checked.
+        self.points_to.solve();
+        let argument_checks = if body.source.def_id().is_local() { self.argument_checks.clone() } else { Vec::new() };
+        for (mut diagnostic, a, b) in argument_checks {
+            if self.points_to.must_alias(&a, &b) {
+                diagnostic.severity = Severity::Error;
+                self.report(diagnostic);
+            } else if self.points_to.may_alias(&a, &b) {
+                self.report(diagnostic);
+            }
         }
-        println!("Main body -- End");
+        debug!("Main body -- End");
     }
 
     // Function Declarations
@@ -60,6 +108,12 @@ impl<'tcx> MirVisitor<'tcx> {
     ) {
         let _ty = local_decl.ty;
         let _mutability = local_decl.mutability;
-        println!("Declaration {:?} {:?}: {:?}", _mutability, local, _ty);
+        debug!("Declaration {:?} {:?}: {:?}", _mutability, local, _ty);
+        // Zero-sized locals, like closures that capture nothing, are never assigned
+        let is_zst = self.tcx.layout_of(self.param_env.and(local_decl.ty)).map_or(false, |layout| layout.is_zst());
+        if is_zst && local.as_usize() > self.body.arg_count {
+            let path = PlacePath::local(local.as_u32());
+            self.stacked_borrows.get_mut(&path).new_ref(Tag::Tagged(local.as_u32()), Permission::Unique, START_BLOCK.start_location());
+        }
     }
 }
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use rustc_index::vec::IndexVec;
+use rustc_middle::mir::{traversal, BasicBlock, Body, START_BLOCK};
+use rustc_middle::mir::terminator::TerminatorKind;
+use log::warn;
+
+use crate::analyzer::AliasBackend;
+use crate::stacked_borrows::BorrowStacks;
+use crate::points_to::PointsToGraph;
+use super::body_visitor::MirVisitor;
+
+// Upper bound of times a single block is visited, in case a loop never stabilizes
+const MAX_BLOCK_VISITS: usize = 64;
+
+// Analysis state flowing along the edges of the control flow graph
+#[derive(Clone)]
+pub struct BlockState {
+    pub stacked_borrows: BorrowStacks,
+    pub alias_graph: PointsToGraph,
+}
+
+impl BlockState {
+    // Merges the state of another predecessor into this one.
+    // Returns true if the state changed.
+    pub fn join(&mut self, other: &BlockState) -> bool {
+        let stack_changed = self.stacked_borrows.join(&other.stacked_borrows);
+        let graph_changed = self.alias_graph.join(&other.alias_graph);
+        stack_changed || graph_changed
+    }
+}
+
+// Worklist algorithm over the basic blocks
+impl<'tcx> MirVisitor<'tcx> {
+    pub fn visit_blocks_to_fixpoint(&mut self, body: &Body<'tcx>) {
+        let basic_blocks = body.basic_blocks();
+        let mut entry_states: IndexVec<BasicBlock, Option<BlockState>> =
+            IndexVec::from_elem(None, basic_blocks);
+        let mut visits: IndexVec<BasicBlock, usize> = IndexVec::from_elem(0, basic_blocks);
+        let mut return_state: Option<BlockState> = None;
+        let mut truncated = false; // A block changed again after its last visit
+
+        entry_states[START_BLOCK] = Some(self.current_state());
+        let mut worklist = VecDeque::new();
+        // The fast mode visits every block once, in reverse postorder so the forward predecessors
+        // of a block are visited before it. What flows back through the loops is not visited again.
+        let single_pass = self.options.alias_backend == AliasBackend::Steensgaard;
+        if single_pass {
+            worklist.extend(traversal::reverse_postorder(body).map(|(block, _)| block));
+        } else {
+            worklist.push_back(START_BLOCK);
+        }
+
+        while let Some(block) = worklist.pop_front() {
+            visits[block] += 1;
+            let entry_state = entry_states[block].clone().unwrap();
+            self.set_state(entry_state);
+
+            let data = &basic_blocks[block];
+            self.visit_basic_block_data(block, data);
+            let exit_state = self.current_state();
+
+            let terminator = data.terminator();
+            if let TerminatorKind::Return = terminator.kind {
+                match &mut return_state {
+                    Some(state) => { state.join(&exit_state); }
+                    None => return_state = Some(exit_state.clone()),
+                }
+            }
+
+            // Propagate the exit state to the successors and enqueue the ones that changed
+            for successor in terminator.successors() {
+                let changed = match &mut entry_states[*successor] {
+                    Some(state) => state.join(&exit_state),
+                    None => {
+                        entry_states[*successor] = Some(exit_state.clone());
+                        true
+                    }
+                };
+                if single_pass || !changed {
+                    continue;
+                }
+                if visits[*successor] >= MAX_BLOCK_VISITS {
+                    truncated = true;
+                } else if !worklist.contains(successor) {
+                    worklist.push_back(*successor);
+                }
+            }
+        }
+
+        if truncated {
+            warn!("The analysis of {} did not reach a fixpoint after {} visits of a block, its results may be incomplete",
+                self.tcx.def_path_str(body.source.def_id()), MAX_BLOCK_VISITS);
+        }
+
+        // The state of the function is the one reaching its return points
+        if let Some(state) = return_state {
+            self.set_state(state);
+        }
+        self.entry_graphs = entry_states.into_iter().map(|state| state.map(|state| state.alias_graph)).collect();
+    }
+
+    pub fn current_state(&self) -> BlockState {
+        BlockState {
+            stacked_borrows: self.stacked_borrows.clone(),
+            alias_graph: self.alias_graph.clone(),
+        }
+    }
+
+    pub fn set_state(&mut self, state: BlockState) {
+        self.stacked_borrows = state.stacked_borrows;
+        self.alias_graph = state.alias_graph;
+    }
+}
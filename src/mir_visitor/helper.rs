@@ -1,46 +1,606 @@
-use rustc_middle::mir::{Place};
-use rustc_middle::mir::Operand;
-use rustc_middle::mir::Mutability::Mut;
+use std::collections::HashMap;
+
+use rustc_middle::mir::{Body, Local, Location, Place, ProjectionElem, Rvalue, START_BLOCK};
+use rustc_middle::mir::{Operand, StatementKind, TerminatorKind};
+use rustc_middle::mir::Mutability::{Mut, Not};
+use rustc_middle::ty::{Instance, InstanceDef, ParamEnv, Ty, TyCtxt, TyKind, TypeAndMut, TypeFoldable};
+use rustc_middle::ty::subst::SubstsRef;
+use rustc_hir::def_id::DefId;
+use rustc_span::{sym, DUMMY_SP};
 
 // use crate::utils::print_mir;
 use crate::stacked_borrows::{*};
+use crate::points_to::{Allocation, PathElem, PlacePath};
+use crate::points_to::{Constraint, PlaceExpr};
+use crate::diagnostics::Diagnostic;
 use super::body_visitor::MirVisitor;
 
+// Calls of the standard library that the analysis models instead of visiting them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HeapCall {
+    Allocate, // Returns a new heap allocation, like `Box::new` or `alloc`
+    Buffer, // Returns a pointer to the heap allocation owned by the first argument, like `Vec::as_mut_ptr`
+}
+
+pub fn heap_call(tcx: TyCtxt, def_id: DefId) -> Option<HeapCall> {
+    if tcx.crate_name(def_id.krate).as_str() != "alloc" {
+        return None;
+    }
+    let owner = tcx.impl_of_method(def_id).map(|impl_id| tcx.type_of(impl_id));
+    let is_box = owner.map_or(false, |ty| ty.is_box());
+    let is_vec = owner.and_then(|ty| ty.ty_adt_def()).map_or(false, |adt| tcx.is_diagnostic_item(sym::Vec, adt.did));
+    // Closures have no name
+    let name = tcx.opt_item_name(def_id)?;
+    match name.as_str() {
+        "alloc" | "alloc_zeroed" | "realloc" | "exchange_malloc" | "from_elem" if owner.is_none() => Some(HeapCall::Allocate),
+        "new" | "pin" if is_box => Some(HeapCall::Allocate),
+        "with_capacity" if is_vec => Some(HeapCall::Allocate),
+        "into_raw" | "leak" if is_box => Some(HeapCall::Buffer),
+        "as_ptr" | "as_mut_ptr" | "as_slice" | "as_mut_slice" if is_vec => Some(HeapCall::Buffer),
+        _ => None,
+    }
+}
+
+// Function called by a call terminator and its generic arguments, if it is known statically
+pub fn called_function<'tcx>(func: &Operand<'tcx>) -> Option<(DefId, SubstsRef<'tcx>)> {
+    match func.constant()?.literal.ty().kind() {
+        TyKind::FnDef(def_id, substs) => Some((*def_id, substs)),
+        _ => None,
+    }
+}
+
+// Functions that may run for a call of a function item in the body of `instance`: the method of
+// the impl for a trait method, or the methods of every impl of the trait for a call on a `dyn Trait`
+pub fn resolve_call<'tcx>(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>, param_env: ParamEnv<'tcx>, func_ty: Ty<'tcx>) -> Vec<Instance<'tcx>> {
+    let func_ty = match instance.try_subst_mir_and_normalize_erasing_regions(tcx, param_env, func_ty) {
+        Ok(func_ty) => func_ty,
+        Err(_) => return Vec::new(),
+    };
+    let callee = match func_ty.kind() {
+        TyKind::FnDef(def_id, substs) => Instance::resolve(tcx, param_env, *def_id, substs).ok().flatten(),
+        _ => None,
+    };
+    match callee {
+        Some(Instance { def: InstanceDef::Virtual(def_id, _), substs }) => dyn_targets(tcx, param_env, def_id, substs),
+        Some(callee) => vec![callee],
+        None => Vec::new(),
+    }
+}
+
+// Methods that may run for a call of a trait method on a `dyn Trait`: the ones of the impls of the
+// trait in the crate, for the same generic arguments of the trait. The impls with generic
+// parameters are left out, their type is not known.
+fn dyn_targets<'tcx>(tcx: TyCtxt<'tcx>, param_env: ParamEnv<'tcx>, method: DefId, substs: SubstsRef<'tcx>) -> Vec<Instance<'tcx>> {
+    let trait_id = match tcx.trait_of_item(method) {
+        Some(trait_id) => trait_id,
+        None => return Vec::new(),
+    };
+    tcx.all_impls(trait_id)
+        .filter(|impl_id| impl_id.is_local() && !tcx.generics_of(*impl_id).requires_monomorphization(tcx))
+        .filter_map(|impl_id| tcx.impl_trait_ref(impl_id).map(|trait_ref| tcx.erase_regions(trait_ref)))
+        .filter(|trait_ref| trait_ref.substs[1..] == substs[1..trait_ref.substs.len()])
+        .filter_map(|trait_ref| {
+            let substs = tcx.mk_substs(std::iter::once(trait_ref.self_ty().into()).chain(substs.iter().skip(1)));
+            Instance::resolve(tcx, param_env, method, substs).ok().flatten()
+        })
+        .collect()
+}
+
+// Function that a function item turned into a function pointer points to. Generic functions
+// are left out, the function pointer does not keep their generic arguments.
+pub fn reified_function<'tcx>(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>, param_env: ParamEnv<'tcx>, func_ty: Ty<'tcx>) -> Option<DefId> {
+    match resolve_call(tcx, instance, param_env, func_ty)[..] {
+        [Instance { def: InstanceDef::Item(def), .. }] if !tcx.generics_of(def.did).requires_monomorphization(tcx) => Some(def.did),
+        _ => None,
+    }
+}
+
+// Drop glue that runs when a value of type `ty` in the body of `instance` is dropped,
+// if the type has something to drop and is known
+pub fn drop_glue<'tcx>(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>, param_env: ParamEnv<'tcx>, ty: Ty<'tcx>) -> Option<Instance<'tcx>> {
+    let ty = instance.try_subst_mir_and_normalize_erasing_regions(tcx, param_env, ty).ok()?;
+    if ty.potentially_needs_subst() {
+        return None;
+    }
+    match Instance::resolve_drop_in_place(tcx, ty) {
+        glue @ Instance { def: InstanceDef::DropGlue(_, Some(_)), .. } => Some(glue),
+        _ => None,
+    }
+}
+
+// Types of the variables captured by a closure or a generator, when the type is one or points to one,
+// like the `&mut self` of a closure body or the `Pin<&mut Self>` of a generator, and whether it is
+// behind a pointer
+pub fn closure_upvars<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> Option<(bool, Vec<Ty<'tcx>>)> {
+    let (closure, behind_pointer) = match pinned_pointer(tcx, ty).unwrap_or(ty).builtin_deref(true) {
+        Some(pointee) => (pointee.ty, true),
+        None => (ty, false),
+    };
+    let upvars = match closure.kind() {
+        TyKind::Closure(_, substs) => substs.as_closure().tupled_upvars_ty(),
+        TyKind::Generator(_, substs, _) => substs.as_generator().tupled_upvars_ty(),
+        _ => return None,
+    };
+    match upvars.kind() {
+        TyKind::Tuple(_) => Some((behind_pointer, upvars.tuple_fields().collect())),
+        _ => None,
+    }
+}
+
+// Pointer held by a `Pin`, in its only field
+pub fn pinned_pointer<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> Option<Ty<'tcx>> {
+    match ty.kind() {
+        TyKind::Adt(adt, substs) if tcx.lang_items().pin_type() == Some(adt.did) => Some(substs.type_at(0)),
+        _ => None,
+    }
+}
+
+// Calls whose callee is analyzed: not expanded from a macro, not a modeled heap function,
+// and with MIR available
+pub fn is_followed_call(tcx: TyCtxt, func: &Operand, callee: Instance) -> bool {
+    let from_expansion = func.constant().map_or(false, |constant| constant.span.from_expansion());
+    let has_mir = match callee.def {
+        InstanceDef::Item(def) => tcx.is_mir_available(def.did),
+        InstanceDef::Intrinsic(_) | InstanceDef::Virtual(..) => false,
+        _ => true, // Shims are built by the compiler
+    };
+    !from_expansion && heap_call(tcx, callee.def_id()).is_none() && has_mir
+}
+
+// Where the value of a raw pointer variable comes from
+enum Provenance {
+    Pointer(Local), // Copy or cast of another raw pointer
+    Integer, // Cast from an integer
+    Own, // Created in the variable, like with `&raw mut x`, or written in another way
+}
+
+// Tags carried by the raw pointer variables of the function that do not get one of their own.
+// A copy or cast of another raw pointer carries the tag of the pointer it was derived from, and
+// a pointer cast from an integer has a wildcard tag. Parameters, and variables that get their
+// value in any other way or whose address is taken, use their own tag.
+pub fn raw_pointer_provenance<'tcx>(tcx: TyCtxt<'tcx>, body: &Body<'tcx>) -> HashMap<u32, Tag> {
+    let mut sources: HashMap<Local, Vec<Provenance>> = HashMap::new();
+    for data in body.basic_blocks() {
+        for statement in &data.statements {
+            if let StatementKind::Assign(assignment) = &statement.kind {
+                let (place, rvalue) = &**assignment;
+                if let Some(local) = place.as_local() {
+                    let source = match rvalue {
+                        Rvalue::Use(operand) | Rvalue::Cast(_, operand, _) => {
+                            let source_ty = operand.ty(body, tcx);
+                            match operand.place().and_then(|source| source.as_local()) {
+                                Some(source) if source_ty.is_unsafe_ptr() => Provenance::Pointer(source),
+                                _ if source_ty.is_integral() && matches!(rvalue, Rvalue::Cast(..)) => Provenance::Integer,
+                                _ => Provenance::Own,
+                            }
+                        }
+                        _ => Provenance::Own,
+                    };
+                    sources.entry(local).or_default().push(source);
+                }
+                // The variable may be written through the pointer to it
+                if let Rvalue::Ref(_, _, borrowed) | Rvalue::AddressOf(_, borrowed) = rvalue {
+                    sources.entry(borrowed.local).or_default().push(Provenance::Own);
+                }
+            }
+        }
+        if let Some(TerminatorKind::Call { destination: Some((place, _)), .. }) = data.terminator.as_ref().map(|terminator| &terminator.kind) {
+            sources.entry(place.local).or_default().push(Provenance::Own);
+        }
+    }
+
+    // Chains of copies are followed until the tags stop changing
+    let mut provenance = HashMap::new();
+    for _ in 0..body.local_decls.len() {
+        let mut changed = false;
+        for (local, sources) in &sources {
+            if (1..=body.arg_count).contains(&local.as_usize()) || !body.local_decls[*local].ty.is_unsafe_ptr() {
+                continue;
+            }
+            let own = Tag::Tagged(local.as_u32());
+            let tags: Vec<Tag> = sources.iter()
+                .map(|source| match source {
+                    Provenance::Pointer(source) => provenance.get(&source.as_u32()).copied().unwrap_or(Tag::Tagged(source.as_u32())),
+                    Provenance::Integer => Tag::Wildcard,
+                    Provenance::Own => own,
+                })
+                .collect();
+            let tag = if tags.contains(&Tag::Wildcard) {
+                Tag::Wildcard
+            } else if tags.iter().all(|tag| *tag == tags[0]) {
+                tags[0]
+            } else {
+                own
+            };
+            if provenance.get(&local.as_u32()).copied().unwrap_or(own) != tag {
+                changed = true;
+                if tag == own {
+                    provenance.remove(&local.as_u32());
+                } else {
+                    provenance.insert(local.as_u32(), tag);
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    provenance
+}
+
 impl<'tcx> MirVisitor<'tcx> {
     // Stacked Borrows helper functions
     pub fn place_to_tag(&self, place: &Place) -> Tag {
-        Tag::Tagged(place.local.as_u32())
+        self.local_tag(place.local.as_u32())
+    }
+
+    pub fn local_tag(&self, local: u32) -> Tag {
+        self.provenance.get(&local).copied().unwrap_or(Tag::Tagged(local))
+    }
+
+    // The variable gets a pointer that was cast from an integer in a callee, so it and its
+    // copies have a wildcard tag from now on
+    pub fn set_wildcard(&mut self, local: u32) {
+        let own = self.local_tag(local);
+        for tag in self.provenance.values_mut().filter(|tag| **tag == own) {
+            *tag = Tag::Wildcard;
+        }
+        self.provenance.insert(local, Tag::Wildcard);
+    }
+
+    // Places accessed through a place: the fields are followed up to the path depth, and
+    // every (*x) is replaced by the places that x may point to
+    pub fn place_paths(&self, place: &Place) -> Vec<PlacePath> {
+        let mut paths = vec![PlacePath::local(place.local.as_u32())];
+        for elem in place.projection {
+            let step = match elem {
+                ProjectionElem::Deref => {
+                    paths = paths.iter().flat_map(|path| self.alias_graph.pointed_allocations(path)).collect();
+                    paths.sort();
+                    paths.dedup();
+                    continue;
+                }
+                ProjectionElem::Downcast(_, variant) => PathElem::Variant(variant.as_u32()),
+                ProjectionElem::Field(field, _) => PathElem::Field(field.as_u32()),
+                ProjectionElem::Index(_) | ProjectionElem::ConstantIndex { .. } | ProjectionElem::Subslice { .. } => PathElem::Index,
+            };
+            paths = paths.iter()
+                .map(|path| if path.projections.len() < self.options.path_depth { path.project(step) } else { path.clone() })
+                .collect();
+        }
+        paths
+    }
+
+    // Place with its dereferences, for the constraints of the points-to solver
+    pub fn place_expr(&self, place: &Place) -> PlaceExpr {
+        let mut expr = PlaceExpr::new(PlacePath::local(place.local.as_u32()));
+        for elem in place.projection {
+            expr = match elem {
+                ProjectionElem::Deref => expr.deref(),
+                ProjectionElem::Downcast(_, variant) => expr.project(PathElem::Variant(variant.as_u32())),
+                ProjectionElem::Field(field, _) => expr.project(PathElem::Field(field.as_u32())),
+                ProjectionElem::Index(_) | ProjectionElem::ConstantIndex { .. } | ProjectionElem::Subslice { .. } => expr.project(PathElem::Index),
+            };
+        }
+        expr
+    }
+
+    pub fn add_constraint(&mut self, constraint: Constraint) {
+        self.points_to.add_constraint(constraint);
     }
 
-    pub fn add_to_stack(&mut self, place: &Place, tag: Tag) {
+    // Places that the pointer stored in a place may point to
+    pub fn pointee_paths(&self, place: &Place) -> Vec<PlacePath> {
+        let mut paths: Vec<PlacePath> = self.place_paths(place).iter()
+            .flat_map(|path| self.alias_graph.pointed_allocations(path))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    pub fn add_to_stack(&mut self, place: &Place, tag: Tag, location: Location) {
         if !place.is_indirect() { // is not a (*x)
-            self.stacked_borrows.new_ref(tag, Permission::Unique);
+            for path in self.place_paths(place) {
+                for stack in self.stacked_borrows.overlapping_mut(&path) {
+                    stack.new_ref(tag, Permission::Unique, location);
+                }
+                // Writing a field also gives the variable access to the places that contain it
+                for len in 0..path.projections.len() {
+                    let parent = PlacePath { allocation: path.allocation, projections: path.projections[..len].to_vec() };
+                    let stack = self.stacked_borrows.get_mut(&parent);
+                    if !stack.contains(tag) {
+                        stack.new_ref(tag, Permission::Unique, location);
+                    }
+                }
+            }
+        }
+        self.use_place(place, tag, location);
+    }
+
+    pub fn use_place(&mut self, place: &Place, tag: Tag, location: Location) {
+        self.access_place(place, tag, AccessKind::Write, location);
+    }
+
+    pub fn read_place(&mut self, place: &Place, tag: Tag, location: Location) {
+        self.access_place(place, tag, AccessKind::Read, location);
+    }
+
+    // Accesses every place the place may be, with all the parts of them that have their own stack
+    fn access_place(&mut self, place: &Place, tag: Tag, access: AccessKind, location: Location) {
+        for path in self.place_paths(place) {
+            let result = self.access_path(&path, tag, access, location);
+            self.check_access(result, path, location);
+        }
+    }
+
+    // Returns the first violation, after updating all the stacks
+    pub fn access_path(&mut self, path: &PlacePath, tag: Tag, access: AccessKind, location: Location) -> Result<(), Violation> {
+        if let Allocation::Unknown(local, _) | Allocation::Upvar(local, ..) = path.allocation {
+            if local as usize <= self.body.arg_count && self.accesses.get(path) != Some(&AccessKind::Write) {
+                self.accesses.insert(path.clone(), access);
+            }
+        }
+        self.stacked_borrows.overlapping_mut(path)
+            .map(|stack| match access {
+                AccessKind::Read => stack.read_value(tag, location),
+                _ => stack.use_value(tag, location),
+            })
+            .fold(Ok(()), |first, result| first.and(result))
+    }
+
+    // Creates a new pointer with `tag` derived from the pointer with `parent` tag
+    // in every place it may point to
+    pub fn derive_pointer(&mut self, paths: Vec<PlacePath>, parent: Tag, tag: Tag, permission: Permission, location: Location) {
+        // The code of a function is not borrowed
+        for path in paths.into_iter().filter(|path| !matches!(path.allocation, Allocation::Function(_))) {
+            let access = match permission {
+                Permission::SharedReadOnly => AccessKind::Read,
+                _ => AccessKind::Write,
+            };
+            let result = self.access_path(&path, parent, access, location);
+            for stack in self.stacked_borrows.overlapping_mut(&path) {
+                stack.new_ref(tag, permission, location);
+            }
+            self.check_access(result, path, location);
+        }
+    }
+
+    // Reborrows the places a pointer of type `ty` points to
+    pub fn reborrow(&mut self, paths: Vec<PlacePath>, ty: Ty<'tcx>, parent: Tag, tag: Tag, permission: Permission, location: Location) {
+        match self.monomorphize(ty).kind() {
+            TyKind::Ref(_, pointee, Not) => self.derive_shared(paths, pointee, parent, tag, location),
+            _ => self.derive_pointer(paths, parent, tag, permission, location),
+        }
+    }
+
+    // Creates a shared reference to places holding a value of type `ty`. The parts of the value
+    // inside an `UnsafeCell`, like the one of a `Cell` or a `Mutex`, can still be written through it,
+    // so it is shared read-write for them and shared read-only for the rest.
+    pub fn derive_shared(&mut self, paths: Vec<PlacePath>, ty: Ty<'tcx>, parent: Tag, tag: Tag, location: Location) {
+        for path in paths.into_iter().filter(|path| !matches!(path.allocation, Allocation::Function(_))) {
+            if ty.is_freeze(self.tcx.at(DUMMY_SP), self.param_env) {
+                self.derive_pointer(vec![path], parent, tag, Permission::SharedReadOnly, location);
+                continue;
+            }
+            let fields: Vec<Ty<'tcx>> = match ty.kind() {
+                TyKind::Adt(adt, substs) if adt.is_struct() && Some(adt.did) != self.tcx.lang_items().unsafe_cell_type() => {
+                    adt.non_enum_variant().fields.iter().map(|field| field.ty(self.tcx, substs)).collect()
+                }
+                TyKind::Tuple(_) => ty.tuple_fields().collect(),
+                _ => Vec::new(),
+            };
+            // Cells, enums and fields deeper than the path depth are interior mutable as a whole
+            if fields.is_empty() || path.projections.len() >= self.options.path_depth {
+                let result = self.stacked_borrows.overlapping_mut(&path)
+                    .map(|stack| stack.new_shared_rw(parent, tag, location))
+                    .fold(Ok(()), |first, result| first.and(result));
+                self.check_access(result, path, location);
+                continue;
+            }
+            let result = self.stacked_borrows.get_mut(&path).new_shared_rw(parent, tag, location);
+            self.check_access(result, path.clone(), location);
+            for (field, field_ty) in (0..).zip(fields) {
+                self.derive_shared(vec![path.project(PathElem::Field(field))], field_ty, parent, tag, location);
+            }
+        }
+    }
+
+    // Creates a two-phase borrow, like the `&mut v` of `v.push(v.len())`. It is reserved without
+    // writing the place, the write happens when the borrow is first written through.
+    pub fn derive_reserved(&mut self, paths: Vec<PlacePath>, parent: Tag, tag: Tag, location: Location) {
+        for path in paths.into_iter().filter(|path| !matches!(path.allocation, Allocation::Function(_))) {
+            let result = self.stacked_borrows.overlapping_mut(&path)
+                .map(|stack| {
+                    let result = stack.new_shared_rw(parent, tag, location);
+                    stack.reserve(tag);
+                    result
+                })
+                .fold(Ok(()), |first, result| first.and(result));
+            self.check_access(result, path, location);
+        }
+    }
+
+    // Casting a pointer to an integer exposes its tag in the places it points to, so the pointers
+    // cast back from integers may use it
+    pub fn expose(&mut self, paths: Vec<PlacePath>, tag: Tag) {
+        for path in paths {
+            if let Allocation::Unknown(local, _) | Allocation::Upvar(local, ..) = path.allocation {
+                if local as usize <= self.body.arg_count {
+                    self.exposed.insert(path.clone());
+                }
+            }
+            for stack in self.stacked_borrows.overlapping_mut(&path) {
+                stack.expose(tag);
+            }
+        }
+    }
+
+    // Copies the raw pointer with `parent` tag to the pointer with `tag` in every place it may point to
+    pub fn copy_raw_pointer(&mut self, paths: Vec<PlacePath>, parent: Tag, tag: Tag, location: Location) {
+        for path in paths.into_iter().filter(|path| !matches!(path.allocation, Allocation::Function(_))) {
+            for stack in self.stacked_borrows.overlapping_mut(&path) {
+                stack.copy_ref(parent, tag, location);
+            }
+        }
+    }
+
+    // Reports the access if it was not allowed by the stack of the place
+    pub fn check_access(&mut self, result: Result<(), Violation>, path: PlacePath, location: Location) {
+        if let Err(violation) = result {
+            self.report(Diagnostic::from_violation(violation, path, self.body, location));
+        }
+    }
+
+    // The place gets a value that does not point anywhere. When the place is a single known
+    // place its previous targets are removed (strong update). When it may be one of several
+    // places, like an array element or the target of a pointer with many targets, each of them
+    // may keep its value, so their targets are kept too (weak update).
+    pub fn assign_constant(&mut self, place: &Place) {
+        if let [path] = &self.place_paths(place)[..] {
+            if !path.is_summary() {
+                self.alias_graph.constant(path);
+            }
+        }
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        if !self.diagnostics.iter().any(|previous| diagnostic.repeats(previous)) {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    // Permission of the tag carried by a value of the given type, if it is a pointer
+    pub fn pointer_permission(&self, ty: Ty<'tcx>) -> Option<Permission> {
+        match ty.kind() {
+            TyKind::Ref(_, _, Mut) => Some(Permission::Unique),
+            TyKind::Ref(_, _, Not) => Some(Permission::SharedReadOnly),
+            TyKind::RawPtr(_) => Some(Permission::SharedReadWrite),
+            _ if ty.is_box() => Some(Permission::Unique),
+            _ => None,
         }
-        self.stacked_borrows.use_value(tag);
     }
 
     pub fn push_args(&mut self) {
-        let mut index = 1;
-        for _arg in &self.args {
-            self.stacked_borrows.new_ref(Tag::Tagged(index), Permission::Unique);
-            self.alias_graph.constant(index);
-            index += 1;
+        // Functions analyzed on their own have no caller operands, so use the signature
+        for index in 1..=self.body.arg_count as u32 {
+            let path = PlacePath::local(index);
+            self.stacked_borrows.get_mut(&path).new_ref(Tag::Tagged(index), Permission::Unique, START_BLOCK.start_location());
+            self.alias_graph.constant(&path);
+            let ty = self.monomorphize(self.body.local_decls[Local::from_u32(index)].ty);
+            // A pinned pointer, like the `Pin<&mut Self>` of a generator, is the pointer in its field
+            let (pointer, pointer_ty) = match pinned_pointer(self.tcx, ty) {
+                Some(pointer_ty) => (path.project(PathElem::Field(0)), pointer_ty),
+                None => (path, ty),
+            };
+            if self.pointer_permission(pointer_ty).is_some() {
+                self.add_unknown_target(PlaceExpr::new(pointer.clone()), Allocation::Unknown(index, 1));
+                self.add_unknown_pointees(pointer, pointer_ty, index, |derefs| Allocation::Unknown(index, derefs));
+            }
+            // The variables a closure captured by reference point to memory of its creator
+            if let Some((behind_pointer, upvars)) = closure_upvars(self.tcx, ty) {
+                let environment = if behind_pointer { PlacePath::new(Allocation::Unknown(index, 1)) } else { PlacePath::local(index) };
+                for (field, upvar_ty) in (0..).zip(upvars) {
+                    if self.pointer_permission(upvar_ty).is_some() {
+                        let pointer = environment.project(PathElem::Field(field));
+                        self.add_unknown_target(PlaceExpr::new(pointer.clone()), Allocation::Upvar(index, field, 1));
+                        self.add_unknown_pointees(pointer, upvar_ty, index, |derefs| Allocation::Upvar(index, field, derefs));
+                    }
+                }
+            }
         }
     }
 
-    // Points-to analisis helper functions
-    pub fn is_mutable(&self, operand: &mut Operand) -> bool {
-        match operand {
-            Operand::Move(place) | Operand::Copy(place) => {
-                let local_decl = self.local_declarations.get(place.local).unwrap();
-                local_decl.mutability == Mut
+    // Type of a local of the body for the generic arguments of the analyzed instance
+    pub fn monomorphize(&self, ty: Ty<'tcx>) -> Ty<'tcx> {
+        self.instance.try_subst_mir_and_normalize_erasing_regions(self.tcx, self.param_env, ty).unwrap_or(ty)
+    }
+
+    // Each level of pointers in the type of a parameter points to its own unknown allocation,
+    // which the parameter can access, so what the function writes through it can be told apart
+    fn add_unknown_pointees(&mut self, path: PlacePath, ty: Ty<'tcx>, local: u32, allocation: impl Fn(u32) -> Allocation) {
+        let (mut pointer, mut ty, mut derefs) = (path, ty, 1);
+        while let Some(permission) = self.pointer_permission(ty) {
+            let unknown = PlacePath::new(allocation(derefs));
+            self.alias_graph.points_to(&pointer, &unknown);
+            self.stacked_borrows.get_mut(&unknown).new_ref(Tag::Tagged(local), permission, START_BLOCK.start_location());
+            ty = match ty.builtin_deref(true) {
+                Some(pointee) => pointee.ty,
+                None => break,
+            };
+            pointer = unknown;
+            derefs += 1;
+        }
+    }
+
+    // The pointer stored in the place points to memory the function does not know about,
+    // which may only point to more unknown memory
+    pub fn add_unknown_target(&mut self, place: PlaceExpr, unknown: Allocation) {
+        let unknown = PlaceExpr::new(PlacePath::new(unknown));
+        self.add_constraint(Constraint::AddressOf { dest: place, target: unknown.clone() });
+        self.add_constraint(Constraint::AddressOf { dest: unknown.clone(), target: unknown });
+    }
+
+    // The place gets a new heap allocation, identified by the location where it is allocated.
+    // Boxes own it through a unique pointer, vectors and `alloc` through a raw pointer.
+    pub fn allocate_heap(&mut self, place: &Place<'tcx>, tag: Tag, location: Location) {
+        self.add_to_stack(place, tag, location);
+        self.assign_constant(place);
+        let heap = PlacePath::new(Allocation::Heap(location));
+        for dest in self.place_paths(place) {
+            self.alias_graph.points_to(&dest, &heap);
+        }
+        self.add_constraint(Constraint::AddressOf { dest: self.place_expr(place), target: PlaceExpr::new(heap.clone()) });
+        let permission = match self.pointer_permission(place.ty(self.body, self.tcx).ty) {
+            Some(Permission::Unique) => Permission::Unique,
+            _ => Permission::SharedReadWrite,
+        };
+        self.stacked_borrows.get_mut(&heap).new_ref(tag, permission, location);
+    }
+
+    // The place gets a pointer to the heap allocations of `owner`, a box or vector or a reference to one,
+    // derived from the tag of the variable that owns them
+    pub fn borrow_buffer(&mut self, place: &Place<'tcx>, owner: &Place<'tcx>, tag: Tag, location: Location) {
+        self.add_to_stack(place, tag, location);
+        self.assign_constant(place);
+        let owner_ty = owner.ty(self.body, self.tcx).ty;
+        let (owners, owner_expr) = match owner_ty.kind() {
+            TyKind::Ref(..) => (
+                self.place_paths(owner).iter().flat_map(|path| self.alias_graph.targets(path)).collect(),
+                self.place_expr(owner).deref(),
+            ),
+            _ => (self.place_paths(owner), self.place_expr(owner)),
+        };
+        self.add_constraint(Constraint::assign(self.place_expr(place), owner_expr));
+        let permission = self.pointer_permission(place.ty(self.body, self.tcx).ty).unwrap_or(Permission::SharedReadWrite);
+        for owner in owners {
+            let heap: Vec<PlacePath> = self.alias_graph.pointed_allocations(&owner).into_iter()
+                .filter(|path| matches!(path.allocation, Allocation::Heap(_)))
+                .collect();
+            for dest in self.place_paths(place) {
+                for path in &heap {
+                    self.alias_graph.points_to(&dest, path);
+                }
             }
-            Operand::Constant(boxed_constant) => {
-                false
+            if let Allocation::Local(local) = owner.allocation {
+                self.derive_pointer(heap, Tag::Tagged(local), tag, permission, location);
             }
         }
     }
 
+    // Points-to analisis helper functions
+    // An argument is mutable if it is a mutable reference or pointer, the mutability of the local
+    // does not matter, every temporary is mutable
+    pub fn is_mutable(&self, operand: &Operand<'tcx>) -> bool {
+        match operand {
+            Operand::Move(_) | Operand::Copy(_) => matches!(
+                operand.ty(self.body, self.tcx).kind(),
+                TyKind::Ref(_, _, Mut) | TyKind::RawPtr(TypeAndMut { mutbl: Mut, .. })
+            ),
+            Operand::Constant(_) => false,
+        }
+    }
+
     pub fn operand_as_u32(&self, operand: &Operand) -> u32 {
         match operand {
             Operand::Move(place) | Operand::Copy(place) => {
@@ -51,4 +611,4 @@ impl<'tcx> MirVisitor<'tcx> {
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,16 +1,22 @@
 use std::collections::HashMap;
 
-use rustc_middle::mir::{Location, Terminator};
+use rustc_middle::mir::{Location, Place, Terminator};
 use rustc_middle::mir::Operand;
+use rustc_middle::mir::Mutability::{Mut, Not};
 use rustc_middle::mir::terminator::TerminatorKind;
-use rustc_middle::mir::ConstantKind;
-use rustc_middle::ty::TyKind;
+use rustc_middle::ty::{Instance, InstanceDef, Ty, TyKind};
 
 
 use petgraph::dot::{Dot, Config};
+use log::{debug, info, warn};
 // use crate::utils::print_mir;
-use crate::stacked_borrows::{*};
+use crate::stacked_borrows::{AccessKind, Permission, Tag};
+use crate::points_to::{Allocation, Constraint, PathElem, PlaceExpr, PlacePath, PointsToGraph};
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Severity};
 use super::body_visitor::MirVisitor;
+use crate::summaries::FunctionSummary;
+use crate::analyzer::visit_function;
+use super::helper::{called_function, closure_upvars, drop_glue, heap_call, is_followed_call, resolve_call, HeapCall};
 
 // Visitor trait implementation
 impl<'tcx> MirVisitor<'tcx> {
@@ -27,55 +33,83 @@ impl<'tcx> MirVisitor<'tcx> {
                 ..
             } => {
 
-                println!("call {:#?}", &func);
+                debug!("call {:#?}", &func);
                 // To-do: analyze function profile, may-alias
 
                 // Visit arg
-                let mut index = 1;
-                let mut arg_refs: HashMap<u32, u32> = HashMap::new();
                 for arg in &args {
                     self.visit_operand(arg, location);
-                    arg_refs.insert(index, self.operand_as_u32(arg));
-                    index+=1;
                 }
 
-                // Check if there are 2 or more mutable arguments with alias
-                let mutable_args: Vec<Operand> = args.clone().drain_filter(|arg| self.is_mutable(arg)).collect();
-                if mutable_args.len() >= 2 {
-                    println!("Caution: This function call contains two or more mutable arguments");
-                    let (a, b) = (self.operand_as_u32(&mutable_args[0]), self.operand_as_u32(&mutable_args[1]));
-                    if self.alias_graph.are_alias(a,b) {
-                        println!("WARNING: Calling function with two mutable arguments that are alias");
+                // Check every pair of mutable arguments for aliases
+                let mutable_args: Vec<u32> = args.iter()
+                    .filter(|arg| self.is_mutable(arg))
+                    .map(|arg| self.operand_as_u32(arg))
+                    .collect();
+                for (i, &a) in mutable_args.iter().enumerate() {
+                    for &b in &mutable_args[i + 1..] {
+                        let diagnostic = Diagnostic {
+                            kind: DiagnosticKind::AliasingMutableArguments,
+                            severity: Severity::Warning,
+                            span: terminator.source_info.span,
+                            location,
+                            function: self.body.source.def_id(),
+                            allocation: None,
+                            allocation_name: None,
+                            tags: vec![Tag::Tagged(a), Tag::Tagged(b)],
+                            invalidated_by: None,
+                            creation_span: None,
+                            invalidation_span: None,
+                        };
+                        self.argument_checks.push((diagnostic, PlacePath::local(a), PlacePath::local(b)));
                     }
                 }
 
                 // Visit inside function
-                let constant = &func.constant().unwrap();
-                if let ConstantKind::Ty(cnst) = constant.literal {
-                    if cnst.ty.is_fn() {
-                        println!("const ty {:#?}", cnst.ty);
-                        if let TyKind::FnDef(def_id, subs_ref) = cnst.ty.kind() {
-                            // Ignore if it's a macro or if the mir is not available
-                            if !constant.span.from_expansion() && self.tcx.is_mir_available(*def_id) {
-                                let body = self.tcx.optimized_mir(*def_id);
-                                let mut visitor = MirVisitor::new(self.tcx, body, args);
-                                visitor.visit_body(body);
-
-                                println!("{:?}", Dot::with_config(&visitor.alias_graph.graph, &[Config::EdgeNoLabel]));
-                                // self.alias_graph.extend(visitor.alias_graph.graph, arg_refs);
-                            }
-                        }
-                    }
+                let heap_call = called_function(&func).and_then(|(def_id, _)| heap_call(self.tcx, def_id));
+                // Every function that may be called, through a function pointer or a `dyn Trait`, does its part
+                let func_ty = func.ty(self.body, self.tcx);
+                let callees = match func_ty.kind() {
+                    TyKind::FnPtr(_) => self.function_pointer_targets(&func),
+                    _ => resolve_call(self.tcx, self.instance, self.param_env, func_ty),
+                };
+                let tcx = self.tcx;
+                let summaries: Vec<(FunctionSummary, Vec<Option<CallArgument>>)> = callees.into_iter()
+                    .filter(|callee| is_followed_call(tcx, &func, *callee))
+                    .map(|callee| (self.callee_summary(callee), self.call_arguments(&args, callee)))
+                    .collect();
+
+                // The callee accesses the memory behind its arguments before it returns
+                let protected = if summaries.is_empty() { Vec::new() } else { self.protect_arguments(&args, location) };
+                for (summary, arguments) in &summaries {
+                    self.report_summary(summary);
+                    self.apply_accesses(summary, arguments, location);
                 }
+                self.unprotect_arguments(protected);
 
                 // Add result variable to stack
                 if let Some((place, _)) = destination {
+                    if let Some(local) = place.as_local().filter(|_| summaries.iter().any(|(summary, _)| summary.wildcard_return)) {
+                        self.set_wildcard(local.as_u32());
+                    }
                     let tag = self.place_to_tag(&place);
-                    if !place.is_indirect() { // place does not contain a Deref
-                        self.stacked_borrows.new_ref(tag, Permission::Unique);
-                        self.alias_graph.constant(place.local.as_u32());
+                    match (heap_call, args.first()) {
+                        (Some(HeapCall::Allocate), _) => self.allocate_heap(&place, tag, location),
+                        (Some(HeapCall::Buffer), Some(Operand::Move(owner) | Operand::Copy(owner))) => {
+                            self.borrow_buffer(&place, owner, tag, location);
+                        }
+                        _ => {
+                            self.assign_constant(&place);
+                            self.add_to_stack(&place, tag, location);
+                            if self.pointer_permission(place.ty(self.body, self.tcx).ty).is_some() {
+                                self.add_unknown_target(self.place_expr(&place), Allocation::Unknown(place.local.as_u32(), 1));
+                            }
+                        }
                     }
-                    self.stacked_borrows.use_value(tag);
+                }
+
+                for (summary, arguments) in &summaries {
+                    self.merge_summary(&summary.alias_graph, arguments, destination.map(|(place, _)| place), location);
                 }
             },
             TerminatorKind::Assert {
@@ -89,30 +123,294 @@ impl<'tcx> MirVisitor<'tcx> {
                 switch_ty,
                 targets
             } => {
-                // println!("SwitchInt {:#?} {:#?} {:#?}", discr, switch_ty, targets);
+                // Targets are followed by the worklist in visit_blocks_to_fixpoint
                 self.visit_operand(&discr, location);
             },
             TerminatorKind::Goto {
                 target
-            } => {
-                // println!("goto {:#?}");
-                // self.visit_basic_block_data(target, self.body.index(target));
-            },
+            } => {},
             TerminatorKind::Drop {
                 place,
                 target,
                 unwind
             } => {
-                self.stacked_borrows.clean();
+                // A type implementing `Drop` runs its destructor on the place first, the glue
+                // gets a pointer to it
+                let tag = self.place_to_tag(&place);
+                let ty = place.ty(self.body, self.tcx).ty;
+                if let Some(glue) = drop_glue(self.tcx, self.instance, self.param_env, ty) {
+                    let summary = self.callee_summary(glue);
+                    let arguments = vec![Some(CallArgument { tag, pointees: self.place_paths(&place), environment: Vec::new() })];
+                    self.report_summary(&summary);
+                    self.apply_accesses(&summary, &arguments, location);
+                    self.merge_summary(&summary.alias_graph, &arguments, None, location);
+                }
+
+                // Dropped allocations have no valid borrows left
+                for path in self.place_paths(&place) {
+                    for stack in self.stacked_borrows.overlapping_mut(&path) {
+                        stack.clean(tag, location);
+                    }
+                }
             }
             TerminatorKind::Return
             | TerminatorKind::Resume
             | TerminatorKind::Unreachable
             => {},
             _ => {
-                println!("Terminator Kind not recognized");
+                debug!("Terminator Kind not recognized");
+            }
+        }
+        debug!("{:#?} Terminator {:#?} | {:?}", location, terminator.kind, self.stacked_borrows);
+    }
+
+    // Summary of a callee, analyzed the first time this instance is called
+    fn callee_summary(&mut self, callee: Instance<'tcx>) -> FunctionSummary {
+        if let Some(summary) = self.summaries.get(callee) {
+            return summary.clone();
+        }
+        // Calls in a cycle are summarized by `summarize_callees` first, this one was not found there
+        if !self.summaries.start(callee) {
+            warn!("Recursive call to {} without a summary", callee);
+            return FunctionSummary::default();
+        }
+        info!("Summarizing {}", callee);
+        let visitor = visit_function(self.tcx, callee, self.param_env, &self.options, &mut self.summaries);
+        debug!("{:?}", Dot::with_config(&visitor.alias_graph.graph, &[Config::EdgeNoLabel]));
+        let summary = visitor.summary();
+        self.summaries.finish(callee);
+        self.summaries.insert(callee, summary.clone());
+        summary
+    }
+
+    // The violations found in the callee, the ones inside other crates like the standard library
+    // are not reported, their code is assumed to be sound
+    fn report_summary(&mut self, summary: &FunctionSummary) {
+        for diagnostic in &summary.diagnostics {
+            if diagnostic.function.is_local() {
+                self.report(diagnostic.clone());
+            }
+        }
+    }
+
+    // Functions a function pointer may point to
+    fn function_pointer_targets(&self, func: &Operand<'tcx>) -> Vec<Instance<'tcx>> {
+        match func {
+            Operand::Move(place) | Operand::Copy(place) => self.targets(&self.place_paths(place)).into_iter()
+                .filter_map(|path| match path.allocation {
+                    Allocation::Function(def_id) => Some(Instance::mono(self.tcx, def_id)),
+                    _ => None,
+                })
+                .collect(),
+            Operand::Constant(_) => Vec::new(),
+        }
+    }
+
+    // Each argument passed in a place, before the call changes it. A closure body takes the
+    // elements of the tuple passed to `Fn::call` as separate arguments.
+    fn call_arguments(&self, args: &[Operand<'tcx>], callee: Instance<'tcx>) -> Vec<Option<CallArgument>> {
+        let spread = matches!(callee.def, InstanceDef::Item(_)) && self.tcx.is_closure(callee.def_id());
+        let mut arguments = Vec::new();
+        for (index, arg) in args.iter().enumerate() {
+            let ty = self.monomorphize(arg.ty(self.body, self.tcx));
+            let place = arg.place();
+            match ty.kind() {
+                TyKind::Tuple(_) if spread && index + 1 == args.len() => {
+                    for (field, field_ty) in (0..).zip(ty.tuple_fields()) {
+                        arguments.push(place.map(|place| {
+                            let places = self.place_paths(&place).into_iter()
+                                .map(|path| if path.projections.len() < self.options.path_depth { path.project(PathElem::Field(field)) } else { path })
+                                .collect();
+                            self.call_argument(self.place_to_tag(&place), places, field_ty)
+                        }));
+                    }
+                }
+                _ => arguments.push(place.map(|place| self.call_argument(self.place_to_tag(&place), self.place_paths(&place), ty))),
+            }
+        }
+        arguments
+    }
+
+    fn call_argument(&self, tag: Tag, places: Vec<PlacePath>, ty: Ty<'tcx>) -> CallArgument {
+        let pointees = self.targets(&places);
+        let environment = match closure_upvars(self.tcx, ty) {
+            Some((true, _)) => pointees.clone(),
+            Some((false, _)) => places,
+            None => Vec::new(),
+        };
+        CallArgument { tag, pointees, environment }
+    }
+
+    fn targets(&self, places: &[PlacePath]) -> Vec<PlacePath> {
+        let mut targets: Vec<PlacePath> = places.iter().flat_map(|place| self.alias_graph.known_targets(place)).collect();
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    // Places reached from an argument after some dereferences, in the graph of the caller
+    fn argument_pointees(&self, args: &[Option<CallArgument>], local: u32, derefs: u32) -> Option<Vec<PlacePath>> {
+        let mut places = parameter(args, local)?.pointees.clone();
+        for _ in 1..derefs {
+            places = self.targets(&places);
+        }
+        Some(places)
+    }
+
+    // Places reached from a variable captured by the closure in an argument after some
+    // dereferences, each with the pointer it was reached from, in the graph of the caller
+    fn upvar_pointees(&self, args: &[Option<CallArgument>], local: u32, field: u32, derefs: u32) -> Option<Vec<(PlacePath, PlacePath)>> {
+        let mut pointers: Vec<PlacePath> = parameter(args, local)?.environment.iter()
+            .map(|path| if path.projections.len() < self.options.path_depth { path.project(PathElem::Field(field)) } else { path.clone() })
+            .collect();
+        let mut pointees = Vec::new();
+        for _ in 0..derefs {
+            pointees = pointers.iter()
+                .flat_map(|pointer| self.alias_graph.known_targets(pointer).into_iter().map(move |target| (pointer.clone(), target)))
+                .collect();
+            pointers = pointees.iter().map(|(_, target)| target.clone()).collect();
+            pointers.sort();
+            pointers.dedup();
+        }
+        Some(pointees)
+    }
+
+    // The callee reads or writes the places its arguments point to with the tags of the arguments.
+    // The tags of the pointers stored deeper in the arguments are not known, so only those are used.
+    // The pointers captured by a closure are stored in variables of the caller, so the places they
+    // point to are accessed with the tags of those variables.
+    // The elements of an array share a stack, so the accesses to them are not checked, two arguments
+    // may borrow different elements. The tags the callee cast to integers are exposed the same way.
+    fn apply_accesses(&mut self, summary: &FunctionSummary, args: &[Option<CallArgument>], location: Location) {
+        for (path, access) in &summary.accesses {
+            for (tag, place) in self.argument_places(path, args) {
+                if !place.projections.contains(&PathElem::Index) {
+                    let result = self.access_path(&place, tag, *access, location);
+                    self.check_access(result, place, location);
+                }
+            }
+        }
+        for path in &summary.exposed {
+            for (tag, place) in self.argument_places(path, args) {
+                self.expose(vec![place], tag);
+            }
+        }
+    }
+
+    // Places of the caller that a path behind a parameter of the callee may be, with the tag
+    // the callee reaches them with
+    fn argument_places(&self, path: &PlacePath, args: &[Option<CallArgument>]) -> Vec<(Tag, PlacePath)> {
+        let pointees: Vec<(Tag, PlacePath)> = match path.allocation {
+            Allocation::Unknown(local, 1) => match parameter(args, local) {
+                Some(argument) => argument.pointees.iter().map(|place| (argument.tag, place.clone())).collect(),
+                None => Vec::new(),
+            },
+            Allocation::Upvar(local, field, derefs) => self.upvar_pointees(args, local, field, derefs).unwrap_or_default().into_iter()
+                .filter_map(|(pointer, place)| Some((owner_tag(&pointer)?, place)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        pointees.into_iter()
+            .map(|(tag, place)| {
+                let place = path.projections.iter().fold(place, |place, elem| {
+                    if place.projections.len() < self.options.path_depth { place.project(*elem) } else { place }
+                });
+                (tag, place)
+            })
+            .collect()
+    }
+
+    // Function-entry retag: each reference argument is used when the call starts, and its tag is
+    // protected in the places it points to until the call returns. Box and raw pointer arguments
+    // are not protected.
+    fn protect_arguments(&mut self, args: &[Operand<'tcx>], location: Location) -> Vec<(Tag, PlacePath)> {
+        let mut protected = Vec::new();
+        for place in args.iter().filter_map(Operand::place) {
+            let access = match self.monomorphize(place.ty(self.body, self.tcx).ty).kind() {
+                TyKind::Ref(_, _, Mut) => AccessKind::Write,
+                TyKind::Ref(_, _, Not) => AccessKind::Read,
+                _ => continue,
+            };
+            let tag = self.place_to_tag(&place);
+            let pointees = self.targets(&self.place_paths(&place)).into_iter()
+                .filter(|path| !path.projections.contains(&PathElem::Index));
+            for path in pointees {
+                let result = self.access_path(&path, tag, access, location);
+                self.check_access(result, path.clone(), location);
+                for stack in self.stacked_borrows.overlapping_mut(&path) {
+                    stack.protect(tag);
+                }
+                protected.push((tag, path));
             }
         }
-        println!("{:#?} Terminator {:#?} | {:#?}", location, terminator.kind, self.stacked_borrows);
+        protected
+    }
+
+    fn unprotect_arguments(&mut self, protected: Vec<(Tag, PlacePath)>) {
+        for (tag, path) in protected {
+            for stack in self.stacked_borrows.overlapping_mut(&path) {
+                stack.unprotect(tag);
+            }
+        }
+    }
+
+    // Applies what the callee did to the memory of the caller: the pointers it stored through
+    // its parameters and the ones it returned. The unknown allocations of the callee are the
+    // places its arguments point to, and the heap objects it allocated are named after the call.
+    fn merge_summary(&mut self, callee: &PointsToGraph, args: &[Option<CallArgument>], destination: Option<Place<'tcx>>, location: Location) {
+        let mut allocations: HashMap<Allocation, Vec<PlacePath>> = HashMap::new();
+        for path in callee.graph.node_weights() {
+            let places = match path.allocation {
+                Allocation::Local(0) => destination.map(|place| self.place_paths(&place)),
+                Allocation::Unknown(local, derefs) => self.argument_pointees(args, local, derefs),
+                Allocation::Upvar(local, field, derefs) => self.upvar_pointees(args, local, field, derefs).map(|pointees| {
+                    let mut places: Vec<PlacePath> = pointees.into_iter().map(|(_, place)| place).collect();
+                    places.sort();
+                    places.dedup();
+                    places
+                }),
+                Allocation::Static(_) | Allocation::Function(_) => Some(vec![path.clone()]),
+                Allocation::Heap(_) => Some(vec![PlacePath::new(Allocation::Heap(location))]),
+                _ => None,
+            };
+            if let Some(places) = places {
+                allocations.insert(path.allocation, places);
+            }
+        }
+
+        for (source, target) in self.alias_graph.extend(callee, &allocations, self.options.path_depth) {
+            self.add_constraint(Constraint::AddressOf { dest: PlaceExpr::new(source.clone()), target: PlaceExpr::new(target.clone()) });
+            // The variables the callee made point somewhere can access that place from the call on
+            if let Allocation::Local(local) = source.allocation {
+                let tag = self.local_tag(local);
+                for stack in self.stacked_borrows.overlapping_mut(&target).filter(|_| tag != Tag::Wildcard) {
+                    if !stack.contains(tag) {
+                        stack.new_ref(tag, Permission::SharedReadWrite, location);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// An argument of a call as the callee sees it: the tag of the pointer it was given, the places
+// it points to in the caller, and the places of the closure it holds or points to, if any
+struct CallArgument {
+    tag: Tag,
+    pointees: Vec<PlacePath>,
+    environment: Vec<PlacePath>,
+}
+
+// Argument received by a parameter of the callee
+fn parameter(args: &[Option<CallArgument>], local: u32) -> Option<&CallArgument> {
+    (local as usize).checked_sub(1).and_then(|index| args.get(index))?.as_ref()
+}
+
+// Tag of the pointer stored in a place of the caller: the one of the variable holding it,
+// or of the parameter for the memory the caller received
+fn owner_tag(pointer: &PlacePath) -> Option<Tag> {
+    match pointer.allocation {
+        Allocation::Local(local) | Allocation::Unknown(local, _) | Allocation::Upvar(local, ..) => Some(Tag::Tagged(local)),
+        _ => None,
     }
 }
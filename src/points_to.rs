@@ -1,11 +1,203 @@
-use std::collections::HashMap;
+use std::{fmt, collections::{BTreeSet, HashMap}};
 
+use rustc_hir::def_id::DefId;
+use rustc_middle::mir::Location;
 use petgraph::graph::{Graph, NodeIndex};
-use petgraph::visit::{Dfs, EdgeRef};
+use petgraph::visit::EdgeRef;
 
+// Abstract memory object. Every local has its own storage, heap objects are identified
+// by the location where they are allocated and statics by their definition. The memory
+// that a parameter or the result of a call points to, which the function cannot see,
+// is named after the local that received the pointer and the number of dereferences
+// needed to reach it, `**x` is `Unknown(x, 2)`. The memory that the variables captured by
+// a closure point to is named after the parameter holding the closure, the captured
+// variable and the dereferences, `*x` for a closure `f` capturing `x` by reference is
+// `Upvar(f, 0, 1)`.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Allocation {
+    Local(u32),
+    Heap(Location),
+    Static(DefId),
+    Unknown(u32, u32),
+    Upvar(u32, u32, u32),
+    Function(DefId), // Code of a function, what function pointers point to
+}
+
+impl fmt::Debug for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Allocation::Local(local) => write!(f, "_{}", local),
+            Allocation::Heap(location) => write!(f, "heap@{:?}", location),
+            Allocation::Static(def_id) => write!(f, "static {:?}", def_id),
+            Allocation::Unknown(local, derefs) => write!(f, "unknown@{}_{}", "*".repeat(*derefs as usize), local),
+            Allocation::Upvar(local, field, derefs) => write!(f, "upvar@{}(_{}.{})", "*".repeat(*derefs as usize), local, field),
+            Allocation::Function(def_id) => write!(f, "fn {:?}", def_id),
+        }
+    }
+}
+
+// Step from a place to a part of it. Dereferences are not steps, they are followed through
+// the graph to the places the pointer may point to.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathElem {
+    Field(u32), // Field of a struct, tuple or union
+    Variant(u32), // Variant of an enum, the places of different variants are separate like the fields of a union
+    Index, // Any element of an array or slice
+}
+
+// Abstract memory place: an allocation and the path followed inside it, like `_2.1`.
+// Places with a common prefix overlap, `_2` contains `_2.1` and `_2.1.0`.
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PlacePath {
+    pub allocation: Allocation,
+    pub projections: Vec<PathElem>,
+}
+
+impl fmt::Debug for PlacePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}{}", self.allocation, self.projections_suffix())
+    }
+}
+
+impl PlacePath {
+    pub fn new(allocation: Allocation) -> PlacePath {
+        PlacePath { allocation, projections: Vec::new() }
+    }
+
+    pub fn local(local: u32) -> PlacePath {
+        PlacePath::new(Allocation::Local(local))
+    }
+
+    #[must_use]
+    pub fn project(&self, elem: PathElem) -> PlacePath {
+        let mut path = self.clone();
+        path.projections.push(elem);
+        path
+    }
+
+    // Path inside the allocation, like `.1[_]`
+    pub fn projections_suffix(&self) -> String {
+        self.projections.iter().map(|elem| match elem {
+            PathElem::Field(field) => format!(".{}", field),
+            PathElem::Variant(variant) => format!("#{}", variant),
+            PathElem::Index => String::from("[_]"),
+        }).collect()
+    }
+
+    // True if `other` is this place or a part of it
+    pub fn contains(&self, other: &PlacePath) -> bool {
+        self.allocation == other.allocation && other.projections.starts_with(&self.projections)
+    }
+
+    pub fn overlaps(&self, other: &PlacePath) -> bool {
+        self.contains(other) || other.contains(self)
+    }
+
+    // True if the place may stand for several places of the running program: the objects
+    // allocated by one call site, the memory received from the caller or the elements of an array
+    pub fn is_summary(&self) -> bool {
+        matches!(self.allocation, Allocation::Heap(_) | Allocation::Unknown(..) | Allocation::Upvar(..)) || self.projections.contains(&PathElem::Index)
+    }
+}
+
+// Place as written in the MIR, with the dereferences that the solver has to resolve.
+// `(*((*_2).0)).1` is the path `_2`, then a dereference followed by the field 0 and
+// another dereference followed by the field 1.
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PlaceExpr {
+    pub base: PlacePath,
+    pub derefs: Vec<Vec<PathElem>>,
+}
+
+impl fmt::Debug for PlaceExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut place = format!("{:?}", self.base);
+        for projections in &self.derefs {
+            let path = PlacePath { allocation: self.base.allocation, projections: projections.clone() };
+            place = format!("(*{}){}", place, path.projections_suffix());
+        }
+        write!(f, "{}", place)
+    }
+}
+
+impl PlaceExpr {
+    pub fn new(base: PlacePath) -> PlaceExpr {
+        PlaceExpr { base, derefs: Vec::new() }
+    }
+
+    pub fn is_indirect(&self) -> bool {
+        !self.derefs.is_empty()
+    }
+
+    #[must_use]
+    pub fn deref(&self) -> PlaceExpr {
+        let mut place = self.clone();
+        place.derefs.push(Vec::new());
+        place
+    }
+
+    #[must_use]
+    pub fn project(&self, elem: PathElem) -> PlaceExpr {
+        let mut place = self.clone();
+        match place.derefs.last_mut() {
+            Some(projections) => projections.push(elem),
+            None => place.base.projections.push(elem),
+        }
+        place
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Constraint {
+    AddressOf { dest: PlaceExpr, target: PlaceExpr }, // dest = &target
+    Copy { dest: PlaceExpr, source: PlaceExpr }, // dest = source
+    Load { dest: PlaceExpr, source: PlaceExpr }, // dest = *source
+    Store { dest: PlaceExpr, source: PlaceExpr }, // *dest = source
+}
+
+impl Constraint {
+    // Copy, load or store depending on the side that dereferences a pointer
+    pub fn assign(dest: PlaceExpr, source: PlaceExpr) -> Constraint {
+        if source.is_indirect() {
+            Constraint::Load { dest, source }
+        } else if dest.is_indirect() {
+            Constraint::Store { dest, source }
+        } else {
+            Constraint::Copy { dest, source }
+        }
+    }
+}
+
+// Points-to analysis of a whole function that answers the alias queries of the checkers.
+// The visitor adds the constraints of every statement and solves them at the end.
+pub trait AliasAnalysis {
+    fn add_constraint(&mut self, constraint: Constraint);
+    fn solve(&mut self);
+    // Places that the pointers stored in `path` may point to
+    fn points_to_set(&self, path: &PlacePath) -> BTreeSet<PlacePath>;
+    // True if the pointers stored in `a` and `b` may point to overlapping places
+    fn may_alias(&self, a: &PlacePath, b: &PlacePath) -> bool;
+
+    // True if the pointers stored in `a` and `b` can only point to the same place. The sets
+    // hold for the whole function, so a pointer with a single target is never reassigned
+    // to point somewhere else.
+    fn must_alias(&self, a: &PlacePath, b: &PlacePath) -> bool {
+        let targets = self.points_to_set(a);
+        targets.len() == 1 && !targets.iter().any(PlacePath::is_summary) && targets == self.points_to_set(b)
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct PointsToGraph {
-    pub graph: Graph::<u32,()>,
-    variables: HashMap<u32, NodeIndex>
+    pub graph: Graph::<PlacePath,()>,
+    variables: HashMap<PlacePath, NodeIndex>
+}
+
+// Two graphs are equal when they have the same edges, wherever their nodes are
+impl PartialEq for PointsToGraph {
+    fn eq(&self, other: &PointsToGraph) -> bool {
+        self.edges() == other.edges()
+    }
 }
 
 impl PointsToGraph {
@@ -17,89 +209,185 @@ impl PointsToGraph {
     }
 
     pub fn get_variable(&self, a: u32) -> NodeIndex<u32> {
-        self.variables.get(&a).unwrap().to_owned()
+        self.get_node(&PlacePath::local(a))
+    }
+
+    pub fn get_node(&self, path: &PlacePath) -> NodeIndex<u32> {
+        self.variables.get(path).unwrap().to_owned()
     }
 
     pub fn does_variable_exits(&self, a: u32) -> bool {
-        match self.variables.get(&a) {
-            Some(variable) => true,
-            None => false,
+        self.does_node_exits(&PlacePath::local(a))
+    }
+
+    pub fn does_node_exits(&self, path: &PlacePath) -> bool {
+        self.variables.contains_key(path)
+    }
+
+    fn get_or_add_node(&mut self, path: &PlacePath) -> NodeIndex<u32> {
+        if self.does_node_exits(path) {
+            self.get_node(path)
+        } else {
+            let node = self.graph.add_node(path.clone());
+            self.variables.insert(path.clone(), node);
+            node
         }
     }
 
-    pub fn constant(&mut self, a: u32) {
-        if self.does_variable_exits(a) {
+    // The place gets a value that points nowhere, neither does any of its fields.
+    // This is a strong update, the previous targets of the place are forgotten.
+    pub fn constant(&mut self, path: &PlacePath) {
+        self.get_or_add_node(path);
+        let nodes: Vec<NodeIndex> = self.variables.iter()
+            .filter(|(node_path, _)| path.contains(node_path))
+            .map(|(_, node)| *node)
+            .collect();
+        for node in nodes {
             // Removes all outgoing edges
-            let graph_clone = self.graph.clone();
-            for edge in graph_clone.edges(self.get_variable(a)) {
-                self.graph.remove_edge(edge.id());
+            let edges: Vec<_> = self.graph.edges(node).map(|edge| edge.id()).collect();
+            for edge in edges {
+                self.graph.remove_edge(edge);
             }
-        } else {
-            self.variables.insert(a, self.graph.add_node(a));
         }
+    }
 
-        // println!("{:?} | Added {}", self.variables, a);
+    pub fn points_to(&mut self, a: &PlacePath, b: &PlacePath) {
+        let node = self.get_or_add_node(a);
+        let target = self.get_or_add_node(b);
+        self.graph.update_edge(node, target, ());
     }
 
-    pub fn points_to(&mut self, a: u32, b: u32) {
-        let node;
-        if self.does_variable_exits(a) {
-            node = self.get_variable(a);
-        } else {
-            node = self.graph.add_node(a);
-            self.variables.insert(a, node);
+    // Places that `path` points to directly
+    pub fn targets(&self, path: &PlacePath) -> Vec<PlacePath> {
+        if !self.does_node_exits(path) {
+            return Vec::new();
+        }
+        self.graph.neighbors(self.get_node(path)).map(|node| self.graph[node].clone()).collect()
+    }
+
+    // Places that a dereference of `path` may access. A place without known targets,
+    // like the result of a call without MIR, stands for its own pointee.
+    pub fn pointed_allocations(&self, path: &PlacePath) -> Vec<PlacePath> {
+        let mut paths = self.known_targets(path);
+        if paths.is_empty() {
+            paths.push(path.clone());
         }
+        paths
+    }
 
-        self.graph.add_edge(node, self.get_variable(b), ());
-        // println!("{:?} | {} points to {}", self.variables, a, b);
+    // Targets of the place, of the places that contain it when the pointer was stored in them
+    // as a whole, and of its fields, like the pointer inside a `Box`. Empty if they are not known.
+    pub fn known_targets(&self, path: &PlacePath) -> Vec<PlacePath> {
+        let mut paths: Vec<PlacePath> = (0..path.projections.len())
+            .map(|len| PlacePath { allocation: path.allocation, projections: path.projections[..len].to_vec() })
+            .flat_map(|prefix| self.targets(&prefix))
+            .chain(self.contained_targets(path))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
     }
 
-    pub fn are_alias(&self, a:u32, b:u32) -> bool {
-        if !self.does_variable_exits(a) || !self.does_variable_exits(b) {
-            return false;
+    // Places that the pointers stored in `path`, or in any of its fields, point to directly
+    pub fn contained_targets(&self, path: &PlacePath) -> Vec<PlacePath> {
+        let mut paths: Vec<PlacePath> = self.variables.keys()
+            .filter(|pointer| path.contains(pointer))
+            .flat_map(|pointer| self.targets(pointer))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    // The pointers stored in `source` and in its fields are copied to the same fields of
+    // `dest`. Fields deeper than `depth` are merged with the field that contains them.
+    pub fn copy(&mut self, dest: &PlacePath, source: &PlacePath, depth: usize) {
+        let copies: Vec<(PlacePath, PlacePath)> = self.variables.keys()
+            .filter(|pointer| pointer.overlaps(source))
+            .flat_map(|pointer| {
+                let mut dest = dest.clone();
+                if source.contains(pointer) {
+                    for elem in &pointer.projections[source.projections.len()..] {
+                        if dest.projections.len() < depth {
+                            dest = dest.project(*elem);
+                        }
+                    }
+                }
+                self.targets(pointer).into_iter().map(move |target| (dest.clone(), target))
+            })
+            .collect();
+        for (dest, target) in copies {
+            self.points_to(&dest, &target);
         }
+    }
 
-        let mut nodes_from_a = Vec::new();
-        let (a, b) = (self.get_variable(a), self.get_variable(b));
+    // Every edge of the graph, sorted
+    pub fn edges(&self) -> Vec<(PlacePath, PlacePath)> {
+        let mut edges: Vec<(PlacePath, PlacePath)> = self.graph.edge_references()
+            .map(|edge| (self.graph[edge.source()].clone(), self.graph[edge.target()].clone()))
+            .collect();
+        edges.sort();
+        edges
+    }
 
-        // Get all extended neighboor nodes starting from A
-        let mut dfs_a = Dfs::new(&self.graph, a);
-        while let Some(node) = dfs_a.next(&self.graph) {
-            nodes_from_a.push(node);
+    // Merges the graph coming from another control flow path, keeping the edges of both.
+    // Returns true if a node or an edge was added.
+    pub fn join(&mut self, other: &PointsToGraph) -> bool {
+        let mut changed = false;
+        for path in other.graph.node_weights() {
+            if !self.does_node_exits(path) {
+                self.get_or_add_node(path);
+                changed = true;
+            }
         }
 
-        // Check if A and B has a common extended neighboor node and return if that's the case
-        let mut dfs_b = Dfs::new(&self.graph, b);
-        while let Some(node) = dfs_b.next(&self.graph) {
-            if nodes_from_a.contains(&node) {
-                return true
+        for edge in other.graph.edge_references() {
+            let a = self.get_node(&other.graph[edge.source()]);
+            let b = self.get_node(&other.graph[edge.target()]);
+            if !self.graph.contains_edge(a, b) {
+                self.graph.add_edge(a, b, ());
+                changed = true;
             }
         }
 
-        // Else return false
-        false
+        changed
     }
 
-    pub fn extend(&mut self, graph: Graph::<u32,()>, args_ref: HashMap<u32, u32>) {
-        let mut variables: HashMap<u32, NodeIndex> = HashMap::new();
-        let cloned_graph = graph.clone();
-        let (nodes, edges) = cloned_graph.into_nodes_edges();
+    // Merges the graph of a callee at its return. `allocations` gives the places of the caller
+    // that each allocation of the callee stands for; the allocations missing from it, like the
+    // locals of the callee, are dropped. The callee may not have run all of its paths, so the
+    // edges are added to the ones of the caller (weak update). Returns the edges that are new.
+    pub fn extend(&mut self, callee: &PointsToGraph, allocations: &HashMap<Allocation, Vec<PlacePath>>, depth: usize) -> Vec<(PlacePath, PlacePath)> {
+        let map = |path: &PlacePath| -> Vec<PlacePath> {
+            allocations.get(&path.allocation).into_iter().flatten()
+                .map(|base| {
+                    let mut mapped = base.clone();
+                    for elem in &path.projections {
+                        if mapped.projections.len() < depth {
+                            mapped = mapped.project(*elem);
+                        }
+                    }
+                    mapped
+                })
+                .collect()
+        };
 
-        for node in nodes {
-            let node_index = self.graph.add_node(node.weight);
-            if args_ref.contains_key(&node.weight) {
-                let real_arg = args_ref.get(&node.weight).unwrap().to_owned();
-                variables.insert(node.weight, self.get_variable(real_arg));
-            } else {
-                variables.insert(node.weight, node_index);
+        let mut edges = Vec::new();
+        for edge in callee.graph.edge_references() {
+            let targets = map(&callee.graph[edge.target()]);
+            for source in map(&callee.graph[edge.source()]) {
+                for target in &targets {
+                    edges.push((source.clone(), target.clone()));
+                }
             }
         }
-
-        for edge in edges {
-            let a = variables.get(graph.node_weight(edge.source()).unwrap()).unwrap().to_owned();
-            let b = variables.get(graph.node_weight(edge.target()).unwrap()).unwrap().to_owned();
-            self.graph.add_edge(a, b, ());
+        edges.sort();
+        edges.dedup();
+        edges.retain(|(source, target)| !self.targets(source).contains(target));
+        for (source, target) in &edges {
+            self.points_to(source, target);
         }
+        edges
     }
 
-}
\ No newline at end of file
+}
@@ -1,14 +1,96 @@
-use std::{fmt, collections::VecDeque};
+use std::{fmt, collections::{BTreeMap, BTreeSet, VecDeque}};
 
+use rustc_middle::mir::Location;
+
+use crate::points_to::PlacePath;
+
+// One borrow stack per abstract place. A place starts sharing the stack of the place that
+// contains it, and gets a stack of its own the first time it is accessed on its own.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct BorrowStacks {
+    stacks: BTreeMap<PlacePath, Stack>
+}
+
+impl fmt::Debug for BorrowStacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.stacks.iter()).finish()
+    }
+}
+
+impl BorrowStacks {
+    pub fn new() -> BorrowStacks {
+        BorrowStacks { stacks: BTreeMap::new() }
+    }
+
+    pub fn get_mut(&mut self, path: &PlacePath) -> &mut Stack {
+        if !self.stacks.contains_key(path) {
+            let stack = self.inherited(path).cloned().unwrap_or_default();
+            self.stacks.insert(path.clone(), stack);
+        }
+        self.stacks.get_mut(path).unwrap()
+    }
+
+    // Stack of the closest place that contains this one, shared by the place until it gets its own
+    fn inherited(&self, path: &PlacePath) -> Option<&Stack> {
+        (0..path.projections.len()).rev()
+            .map(|len| PlacePath { allocation: path.allocation, projections: path.projections[..len].to_vec() })
+            .find_map(|parent| self.stacks.get(&parent))
+    }
+
+    // Stacks of the place and of the parts of it that have a stack of their own,
+    // an access to the place accesses all of them
+    pub fn overlapping_mut<'a>(&'a mut self, path: &'a PlacePath) -> impl Iterator<Item = &'a mut Stack> + 'a {
+        self.get_mut(path);
+        self.stacks.range_mut(path.clone()..)
+            .take_while(move |(other, _)| path.contains(other))
+            .map(|(_, stack)| stack)
+    }
+
+    // Merges the stacks coming from another control flow path. A place with a stack of its own
+    // in only one of the paths is joined with the stack it inherits in the other one, and
+    // allocations that only exist in one of the paths keep their stack. Returns true if any stack changed.
+    pub fn join(&mut self, other: &BorrowStacks) -> bool {
+        let added: Vec<(PlacePath, Stack)> = other.stacks.iter()
+            .filter(|(path, _)| !self.stacks.contains_key(path))
+            .map(|(path, other_stack)| {
+                let mut stack = other_stack.clone();
+                if let Some(inherited) = self.inherited(path) {
+                    stack.join(inherited);
+                }
+                (path.clone(), stack)
+            })
+            .collect();
+        let mut changed = !added.is_empty();
+        for (path, stack) in self.stacks.iter_mut() {
+            if let Some(other_stack) = other.stacks.get(path).or_else(|| other.inherited(path)) {
+                changed |= stack.join(other_stack);
+            }
+        }
+        self.stacks.extend(added);
+        changed
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Eq)]
 pub struct Stack {
-    borrows: VecDeque<StackItem>
+    borrows: VecDeque<StackItem>,
+    // Location where each tag was pushed to the stack
+    created: BTreeMap<Tag, Location>,
+    // Last access that removed each tag from the stack
+    invalidations: BTreeMap<Tag, Invalidation>,
+    // Two-phase borrows that were not written through yet
+    reserved: BTreeSet<Tag>,
+    // Tags of the reference arguments of the call that is running
+    protected: BTreeSet<Tag>,
+    // Tags of the pointers that were cast to integers
+    exposed: BTreeSet<Tag>,
 }
 
 impl fmt::Debug for Stack {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "[")?;
         for item in &self.borrows {
-            if item == *&self.borrows.back().unwrap() {
+            if item == self.borrows.back().unwrap() {
                 write!(f, "{:?}", item)?;
             } else {
                 write!(f, "{:?}, ", item)?;
@@ -40,10 +122,24 @@ pub enum Permission {
     SharedReadWrite, // Grants shared mutable access.
     SharedReadOnly, // Grants shared read-only access.
 }
-#[derive(Copy, Clone, Hash, PartialEq, Eq)]
+
+impl Permission {
+    // Permission that grants only what both permissions grant
+    #[must_use]
+    pub fn weaker(self, other: Permission) -> Permission {
+        match (self, other) {
+            (Permission::SharedReadOnly, _) | (_, Permission::SharedReadOnly) => Permission::SharedReadOnly,
+            (Permission::SharedReadWrite, _) | (_, Permission::SharedReadWrite) => Permission::SharedReadWrite,
+            _ => Permission::Unique,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Tag {
     Tagged(PtrId),
     Untagged,
+    Wildcard, // Pointer cast from an integer, it may have the tag of any exposed pointer
 }
 
 impl fmt::Debug for Tag {
@@ -51,61 +147,269 @@ impl fmt::Debug for Tag {
         match self {
             Tag::Tagged(id) => write!(f, "<{}>", id),
             Tag::Untagged => write!(f, "<untagged>"),
+            Tag::Wildcard => write!(f, "<wildcard>"),
         }
     }
 }
 
 pub type PtrId = u32;
 
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Deallocation,
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessKind::Read => write!(f, "read access"),
+            AccessKind::Write => write!(f, "write access"),
+            AccessKind::Deallocation => write!(f, "deallocation"),
+        }
+    }
+}
+
+// Access made with `tag` at `location` that removed other tags from a stack
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Invalidation {
+    pub tag: Tag,
+    pub access: AccessKind,
+    pub location: Location,
+}
+
+// Access with a tag that is not in the stack anymore, or that removed a protected tag from it
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Violation {
+    pub tag: Tag,
+    pub access: AccessKind,
+    pub created_at: Option<Location>,
+    pub invalidated_by: Option<Invalidation>,
+    pub protected: bool,
+}
+
 impl Stack {
     pub fn new() -> Stack {
-        Stack { borrows: VecDeque::new() }
+        Stack { borrows: VecDeque::new(), created: BTreeMap::new(), invalidations: BTreeMap::new(), reserved: BTreeSet::new(), protected: BTreeSet::new(), exposed: BTreeSet::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.borrows.is_empty()
     }
 
-    pub fn clean(&mut self) {
-        self.borrows.clear();
+    pub fn contains(&self, tag: Tag) -> bool {
+        self.borrows.iter().any(|item| item.tag == tag)
     }
 
-    pub fn new_ref(&mut self, tag: Tag, permission: Permission) {
+    // Casting a pointer to an integer exposes its tag, pointers cast back from integers may use it
+    pub fn expose(&mut self, tag: Tag) {
+        if self.contains(tag) {
+            self.exposed.insert(tag);
+        }
+    }
+
+    // Position of the item of a tag. A wildcard tag has the position of the topmost exposed item,
+    // the one that allows the most without a violation.
+    fn position(&self, tag: Tag) -> Option<usize> {
+        self.borrows.iter().position(|item| item.tag == tag).or_else(|| match tag {
+            Tag::Wildcard => self.borrows.iter().position(|item| self.exposed.contains(&item.tag)),
+            _ => None,
+        })
+    }
+
+    pub fn clean(&mut self, tag: Tag, location: Location) {
+        for item in self.borrows.drain(..) {
+            self.invalidations.insert(item.tag, Invalidation { tag, access: AccessKind::Deallocation, location });
+        }
+    }
+
+    pub fn new_ref(&mut self, tag: Tag, permission: Permission, location: Location) {
         let new_item = StackItem::new(tag, permission);
-        if self.borrows.contains(&new_item) {
-            self.use_value(tag);
+        self.reserved.remove(&tag);
+        if let Some(position) = self.borrows.iter().position(|item| *item == new_item) {
+            self.pop_above(position, tag, location);
             return;
         }
+        self.invalidations.remove(&tag);
+        self.created.insert(tag, location);
         self.borrows.push_front(new_item);
     }
 
-    pub fn use_value(&mut self, tag: Tag) {
-        loop  {
-            let last_item = self.borrows.pop_front();
-            match last_item {
-                Some(item) => {
-                    if item.tag == tag {
-                        self.borrows.push_front(item);
-                        break;
-                    }
-                }
-                None => {
-                    println!("ERROR Tag {:?} does not have write access ERROR", tag);
-                    break;
+    // A shared reference to the inside of an `UnsafeCell` may write like a raw pointer. It goes
+    // above the shared read-write items on top of its parent without accessing the place, so the
+    // other pointers to the cell stay valid.
+    pub fn new_shared_rw(&mut self, parent: Tag, tag: Tag, location: Location) -> Result<(), Violation> {
+        if parent == tag {
+            return Ok(());
+        }
+        self.borrows.retain(|item| item.tag != tag);
+        let position = match self.position(parent) {
+            Some(position) => position,
+            None => return Err(self.violation(parent, AccessKind::Read)),
+        };
+        let shared = self.borrows.iter().take(position).rev().take_while(|item| item.permission == Permission::SharedReadWrite).count();
+        self.borrows.insert(position - shared, StackItem::new(tag, Permission::SharedReadWrite));
+        self.reserved.remove(&tag);
+        self.invalidations.remove(&tag);
+        self.created.insert(tag, location);
+        Ok(())
+    }
+
+    // A copy of a raw pointer is the same pointer, its tag goes right above the one it was copied
+    // from without accessing the place, and it is invalid if the original was
+    pub fn copy_ref(&mut self, parent: Tag, tag: Tag, location: Location) {
+        if parent == tag {
+            return;
+        }
+        self.reserved.remove(&tag);
+        self.borrows.retain(|item| item.tag != tag);
+        match self.position(parent) {
+            Some(position) => {
+                let permission = self.borrows[position].permission;
+                self.borrows.insert(position, StackItem::new(tag, permission));
+                self.invalidations.remove(&tag);
+            }
+            None => {
+                if let Some(invalidation) = self.invalidations.get(&parent).copied() {
+                    self.invalidations.insert(tag, invalidation);
                 }
+            }
+        }
+        self.created.insert(tag, location);
+    }
+
+    // The tag of a reference argument is protected while the call runs: removing it from
+    // the stack is a violation even if the callee never uses it again
+    pub fn protect(&mut self, tag: Tag) {
+        if self.contains(tag) {
+            self.protected.insert(tag);
+        }
+    }
+
+    pub fn unprotect(&mut self, tag: Tag) {
+        self.protected.remove(&tag);
+    }
 
+    // A two-phase borrow, like the `&mut v` of `v.push(v.len())`, is a shared read-write item
+    // that reads through its parent keep until the borrow is written through for the first time
+    pub fn reserve(&mut self, tag: Tag) {
+        if self.contains(tag) {
+            self.reserved.insert(tag);
+        }
+    }
+
+    pub fn use_value(&mut self, tag: Tag, location: Location) -> Result<(), Violation> {
+        match self.position(tag) {
+            Some(position) => {
+                // Writing through a shared read-write item keeps the shared read-write items right above it
+                let shared = if self.borrows[position].permission == Permission::SharedReadWrite {
+                    self.borrows.iter().take(position).rev().take_while(|item| item.permission == Permission::SharedReadWrite).count()
+                } else {
+                    0
+                };
+                let protector = self.pop_above(position - shared, tag, location);
+                // The first write through a two-phase borrow activates it as a unique borrow
+                if self.reserved.remove(&tag) {
+                    self.borrows[shared].permission = Permission::Unique;
+                }
+                protector.map_or(Ok(()), Err)
             }
+            None => Err(self.violation(tag, AccessKind::Write)),
         }
     }
 
-    pub fn read_value(&mut self, tag: Tag) {
+    pub fn read_value(&mut self, tag: Tag, location: Location) -> Result<(), Violation> {
+        let position = match self.position(tag) {
+            Some(position) => position,
+            None => return Err(self.violation(tag, AccessKind::Read)),
+        };
+
+        // Reading disables the unique borrows above the tag
         let mut index = 0;
-        for item in self.borrows.clone().into_iter(){
-            if item.tag == tag {
-                return;
+        let mut protector = None;
+        let invalidation = Invalidation { tag, access: AccessKind::Read, location };
+        let (invalidations, protected, created) = (&mut self.invalidations, &self.protected, &self.created);
+        self.borrows.retain(|item| {
+            let keep = index >= position || item.permission != Permission::Unique;
+            if !keep {
+                invalidations.insert(item.tag, invalidation);
+                if protector.is_none() && protected.contains(&item.tag) {
+                    protector = Some(protector_violation(item.tag, invalidation, created));
+                }
+            }
+            index += 1;
+            keep
+        });
+        protector.map_or(Ok(()), Err)
+    }
+
+    // Merges the stack coming from another control flow path. Only the tags that are
+    // valid in both paths survive, so a tag popped in any predecessor is considered popped,
+    // and each one keeps the weaker of its permissions. Returns true if the stack changed.
+    pub fn join(&mut self, other: &Stack) -> bool {
+        let previous = self.borrows.clone();
+        self.borrows.retain(|item| other.contains(item.tag));
+        for item in self.borrows.iter_mut() {
+            if let Some(other_item) = other.borrows.iter().find(|other_item| other_item.tag == item.tag) {
+                item.permission = item.permission.weaker(other_item.permission);
+            }
+        }
+        let mut changed = self.borrows != previous;
+
+        for (tag, location) in &other.created {
+            if !self.created.contains_key(tag) {
+                self.created.insert(*tag, *location);
+                changed = true;
             }
-            if item.permission == Permission::Unique {
-                self.borrows.remove(index);
+        }
+        for (tag, invalidation) in &other.invalidations {
+            if !self.invalidations.contains_key(tag) {
+                self.invalidations.insert(*tag, *invalidation);
+                changed = true;
             }
-            index += 0;
         }
-        println!("ERROR Tag {:?} does not have READ access ERROR", tag);
+        for tag in &other.reserved {
+            changed |= self.reserved.insert(*tag);
+        }
+        for tag in &other.exposed {
+            changed |= self.exposed.insert(*tag);
+        }
+        changed
+    }
+
+    // Write access, removes every item above the one in `position`. Returns the violation
+    // of the first protected tag removed.
+    fn pop_above(&mut self, position: usize, tag: Tag, location: Location) -> Option<Violation> {
+        let mut protector = None;
+        let invalidation = Invalidation { tag, access: AccessKind::Write, location };
+        for item in self.borrows.drain(..position) {
+            self.invalidations.insert(item.tag, invalidation);
+            if protector.is_none() && self.protected.contains(&item.tag) {
+                protector = Some(protector_violation(item.tag, invalidation, &self.created));
+            }
+        }
+        protector
+    }
+
+    fn violation(&self, tag: Tag, access: AccessKind) -> Violation {
+        Violation {
+            tag,
+            access,
+            created_at: self.created.get(&tag).copied(),
+            invalidated_by: self.invalidations.get(&tag).copied(),
+            protected: false,
+        }
+    }
+}
+
+fn protector_violation(tag: Tag, invalidation: Invalidation, created: &BTreeMap<Tag, Location>) -> Violation {
+    Violation {
+        tag,
+        access: invalidation.access,
+        created_at: created.get(&tag).copied(),
+        invalidated_by: Some(invalidation),
+        protected: true,
     }
 }
 
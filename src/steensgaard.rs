@@ -0,0 +1,170 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::points_to::{AliasAnalysis, Allocation, Constraint, PlaceExpr, PlacePath};
+
+// Unification based points-to analysis. Every allocation belongs to a class of places
+// that may be pointed to by the same pointers, and all the pointers stored in a class point
+// to a single class. Fields are not distinguished. It is less precise than `Andersen`,
+// but each constraint is processed once, in almost linear time: the classes are merged
+// by rank and their paths are compressed.
+#[derive(Clone, Debug, Default)]
+pub struct Steensgaard {
+    constraints: BTreeSet<Constraint>,
+    parents: Vec<usize>, // Union-find forest of the classes
+    ranks: Vec<u32>, // Upper bound of the height of the tree of each root
+    members: Vec<Vec<Allocation>>, // Allocations of each class, kept in its root
+    pointees: Vec<Option<usize>>, // Class pointed to by the pointers of each class, set in its root
+    nodes: HashMap<Allocation, usize>,
+}
+
+impl AliasAnalysis for Steensgaard {
+    fn add_constraint(&mut self, constraint: Constraint) {
+        self.constraints.insert(constraint);
+    }
+
+    fn solve(&mut self) {
+        let constraints: Vec<Constraint> = self.constraints.iter().cloned().collect();
+        for constraint in constraints {
+            match constraint {
+                Constraint::AddressOf { dest, target } => {
+                    let dest = self.class(&dest);
+                    let target = self.class(&target);
+                    let pointee = self.pointee(dest);
+                    self.union(pointee, target);
+                }
+                Constraint::Copy { dest, source } | Constraint::Load { dest, source } | Constraint::Store { dest, source } => {
+                    let dest = self.class(&dest);
+                    let source = self.class(&source);
+                    let (dest_pointee, source_pointee) = (self.pointee(dest), self.pointee(source));
+                    self.union(dest_pointee, source_pointee);
+                }
+            }
+        }
+        // Every node points to its root, so the queries find the classes in one step
+        for node in 0..self.parents.len() {
+            self.find(node);
+        }
+    }
+
+    // Allocations in the class pointed to by the class of `path`
+    fn points_to_set(&self, path: &PlacePath) -> BTreeSet<PlacePath> {
+        let pointee = match self.known_pointee(path) {
+            Some(pointee) => pointee,
+            None => return BTreeSet::new(),
+        };
+        self.members[pointee].iter().map(|allocation| PlacePath::new(*allocation)).collect()
+    }
+
+    fn may_alias(&self, a: &PlacePath, b: &PlacePath) -> bool {
+        match (self.known_pointee(a), self.known_pointee(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    // The fields of an allocation are merged, so two pointers to the same class may still
+    // point to different fields. Only the may-alias query is answered.
+    fn must_alias(&self, _a: &PlacePath, _b: &PlacePath) -> bool {
+        false
+    }
+}
+
+impl Steensgaard {
+    pub fn new() -> Steensgaard {
+        Steensgaard::default()
+    }
+
+    fn add_node(&mut self, allocation: Option<Allocation>) -> usize {
+        let node = self.parents.len();
+        self.parents.push(node);
+        self.ranks.push(0);
+        self.members.push(allocation.into_iter().collect());
+        self.pointees.push(None);
+        node
+    }
+
+    fn node(&mut self, allocation: Allocation) -> usize {
+        match self.nodes.get(&allocation) {
+            Some(node) => *node,
+            None => {
+                let node = self.add_node(Some(allocation));
+                self.nodes.insert(allocation, node);
+                node
+            }
+        }
+    }
+
+    // Root of the class of a node. The nodes on the way are moved under the root.
+    fn find(&mut self, node: usize) -> usize {
+        let root = self.root(node);
+        let mut node = node;
+        while self.parents[node] != root {
+            let parent = self.parents[node];
+            self.parents[node] = root;
+            node = parent;
+        }
+        root
+    }
+
+    fn root(&self, mut node: usize) -> usize {
+        while self.parents[node] != node {
+            node = self.parents[node];
+        }
+        node
+    }
+
+    // Class of the places that a place expression may be
+    fn class(&mut self, place: &PlaceExpr) -> usize {
+        let node = self.node(place.base.allocation);
+        let mut class = self.find(node);
+        for _ in &place.derefs {
+            class = self.pointee(class);
+        }
+        class
+    }
+
+    // Class pointed to by a class, a new one if its pointers do not point anywhere yet
+    fn pointee(&mut self, class: usize) -> usize {
+        let root = self.find(class);
+        match self.pointees[root] {
+            Some(pointee) => self.find(pointee),
+            None => {
+                let pointee = self.add_node(None);
+                self.pointees[root] = Some(pointee);
+                pointee
+            }
+        }
+    }
+
+    fn known_pointee(&self, path: &PlacePath) -> Option<usize> {
+        let node = *self.nodes.get(&path.allocation)?;
+        self.pointees[self.root(node)].map(|pointee| self.root(pointee))
+    }
+
+    // Merges two classes, and then the classes they point to. The root of the lower tree
+    // goes under the other root, and the shorter member list is moved to the longer one.
+    fn union(&mut self, a: usize, b: usize) {
+        let mut pending = vec![(a, b)];
+        while let Some((a, b)) = pending.pop() {
+            let (a, b) = (self.find(a), self.find(b));
+            if a == b {
+                continue;
+            }
+            let (root, child) = if self.ranks[a] < self.ranks[b] { (b, a) } else { (a, b) };
+            if self.ranks[root] == self.ranks[child] {
+                self.ranks[root] += 1;
+            }
+            self.parents[child] = root;
+            if self.members[root].len() < self.members[child].len() {
+                self.members.swap(root, child);
+            }
+            let members = std::mem::take(&mut self.members[child]);
+            self.members[root].extend(members);
+            match (self.pointees[root], self.pointees[child]) {
+                (Some(pointee_root), Some(pointee_child)) => pending.push((pointee_root, pointee_child)),
+                (None, Some(pointee_child)) => self.pointees[root] = Some(pointee_child),
+                _ => {}
+            }
+        }
+    }
+}
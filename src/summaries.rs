@@ -0,0 +1,48 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use rustc_middle::ty::Instance;
+
+use crate::diagnostics::Diagnostic;
+use crate::points_to::{PlacePath, PointsToGraph};
+use crate::stacked_borrows::AccessKind;
+
+// Effect of a function on the memory of its callers, computed once and applied at every call
+#[derive(Clone, Default, PartialEq)]
+pub struct FunctionSummary {
+    pub alias_graph: PointsToGraph, // Points-to graph at the return of the function
+    pub accesses: BTreeMap<PlacePath, AccessKind>, // Strongest access to the memory behind each parameter
+    pub diagnostics: Vec<Diagnostic>, // Findings inside the function, reported again by each caller
+    pub exposed: BTreeSet<PlacePath>, // Memory behind the parameters whose address was cast to an integer
+    pub wildcard_return: bool, // The returned pointer was cast from an integer
+}
+
+// Summaries of the functions already analyzed, by instance: the definition, or the shim like
+// a drop glue, and its generic arguments
+#[derive(Clone, Default)]
+pub struct SummaryCache<'tcx> {
+    summaries: HashMap<Instance<'tcx>, FunctionSummary>,
+    in_progress: HashSet<Instance<'tcx>>, // Functions being analyzed, a call to them is recursive
+}
+
+impl<'tcx> SummaryCache<'tcx> {
+    pub fn new() -> SummaryCache<'tcx> {
+        SummaryCache::default()
+    }
+
+    pub fn get(&self, instance: Instance<'tcx>) -> Option<&FunctionSummary> {
+        self.summaries.get(&instance)
+    }
+
+    pub fn insert(&mut self, instance: Instance<'tcx>, summary: FunctionSummary) {
+        self.summaries.insert(instance, summary);
+    }
+
+    // Marks the function as being analyzed. Returns false if it already was.
+    pub fn start(&mut self, instance: Instance<'tcx>) -> bool {
+        self.in_progress.insert(instance)
+    }
+
+    pub fn finish(&mut self, instance: Instance<'tcx>) {
+        self.in_progress.remove(&instance);
+    }
+}
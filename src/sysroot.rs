@@ -0,0 +1,92 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str;
+
+use log::{info, warn};
+
+use crate::cli::flag_values;
+
+// Finds the sysroot with the standard library the analyzed crates are compiled against.
+// The candidates are tried in this order:
+// 1. The `--sysroot` flag passed to rustc
+// 2. The `SYSROOT` environment variable
+// 3. The toolchain selected with rustup at runtime, through `RUSTUP_TOOLCHAIN`
+// 4. The toolchain the analyzer was built with
+// 5. The sysroot printed by `rustc`
+// The ones that are not given explicitly must be from the same compiler the analyzer is linked to.
+pub fn find_sysroot(rustc_args: &[String], rustc: &str) -> Result<PathBuf, String> {
+    let mut tried: Vec<String> = Vec::new();
+
+    let explicit = [("--sysroot", flag_values(rustc_args, &["--sysroot"]).pop()), ("SYSROOT", env::var("SYSROOT").ok())];
+    for (source, sysroot) in explicit {
+        if let Some(sysroot) = sysroot {
+            if is_sysroot(Path::new(&sysroot)) {
+                if !is_compatible(Path::new(&sysroot)) {
+                    warn!("The sysroot {} is not from the compiler the analyzer was built with", sysroot);
+                }
+                return Ok(PathBuf::from(sysroot));
+            }
+            return Err(format!("{} points to {}, which is not a sysroot", source, sysroot));
+        }
+    }
+
+    let candidates = [
+        ("runtime rustup toolchain", rustup_toolchain(env::var("RUSTUP_HOME").ok(), env::var("RUSTUP_TOOLCHAIN").ok())),
+        ("build toolchain", build_toolchain()),
+        ("rustc --print=sysroot", print_sysroot(rustc)),
+    ];
+    for (source, sysroot) in candidates {
+        match sysroot {
+            Some(sysroot) if is_sysroot(&sysroot) && is_compatible(&sysroot) => {
+                info!("Using the sysroot of the {}: {}", source, sysroot.display());
+                return Ok(sysroot);
+            }
+            Some(sysroot) => tried.push(format!("{} ({})", sysroot.display(), source)),
+            None => {}
+        }
+    }
+
+    Err(format!(
+        "cannot find a sysroot for {}, tried: [{}]. \
+        Install it with `rustup toolchain install {} --component rustc-dev`, \
+        or pass its path with `--sysroot` or the `SYSROOT` environment variable",
+        rustc_interface::util::version_str().unwrap_or("the rustc the analyzer was built with"),
+        tried.join(", "),
+        option_env!("RUSTUP_TOOLCHAIN").unwrap_or("<toolchain>"),
+    ))
+}
+
+fn rustup_toolchain(home: Option<String>, toolchain: Option<String>) -> Option<PathBuf> {
+    let home = home.map(PathBuf::from).or_else(|| env::var("HOME").ok().map(|home| Path::new(&home).join(".rustup")))?;
+    Some(home.join("toolchains").join(toolchain?))
+}
+
+// Rustup sets these variables when it runs cargo, so they describe the toolchain of the build
+fn build_toolchain() -> Option<PathBuf> {
+    rustup_toolchain(option_env!("RUSTUP_HOME").map(String::from), option_env!("RUSTUP_TOOLCHAIN").map(String::from))
+}
+
+fn print_sysroot(rustc: &str) -> Option<PathBuf> {
+    let out = Command::new(rustc).arg("--print=sysroot").output().ok()?;
+    Some(PathBuf::from(str::from_utf8(&out.stdout).ok()?.trim()))
+}
+
+fn is_sysroot(path: &Path) -> bool {
+    path.join("lib").join("rustlib").is_dir()
+}
+
+// The standard library of other compiler versions cannot be loaded by the analyzer
+fn is_compatible(sysroot: &Path) -> bool {
+    let commit_hash = match rustc_interface::util::commit_hash_str() {
+        Some(commit_hash) => commit_hash,
+        None => return true,
+    };
+    let out = match Command::new(sysroot.join("bin").join("rustc")).arg("-vV").output() {
+        Ok(out) => out,
+        Err(_) => return false,
+    };
+    str::from_utf8(&out.stdout).map_or(false, |version| {
+        version.lines().any(|line| line == format!("commit-hash: {}", commit_hash))
+    })
+}
@@ -1,17 +1,18 @@
 use log::{info, error};
 use rustc_middle::ty::{ TyCtxt };
 use rustc_middle::mir::pretty::write_mir_pretty;
+use rustc_middle::mir::{Body, ProjectionElem, VarDebugInfoContents};
 use rustc_hir::def_id::DefId;
 
 // println!("{:#?}", _);
 
-pub fn print_mir<'tcx>(tcx: TyCtxt<'tcx>, def_id: DefId) {
+pub fn print_mir(tcx: TyCtxt, def_id: DefId) {
     println!("Printing MIR for {:?}", def_id);
 
     if tcx.is_mir_available(def_id) {
         let stdout = std::io::stdout();
         let mut handle = stdout.lock();
-        if let Err(_) = write_mir_pretty(tcx, Some(def_id), &mut handle) {
+        if write_mir_pretty(tcx, Some(def_id), &mut handle).is_err() {
             error!(
                 "Cannot print MIR: error while printing `{:?}`",
                 def_id
@@ -21,3 +22,30 @@ pub fn print_mir<'tcx>(tcx: TyCtxt<'tcx>, def_id: DefId) {
         info!("Cannot print MIR: no MIR for `{:?}`", def_id);
     }
 }
+
+// Name of the user variable stored in a local, if it has one
+pub fn local_name(body: &Body, local: u32) -> Option<String> {
+    body.var_debug_info.iter().find_map(|info| match info.value {
+        VarDebugInfoContents::Place(place) if place.local.as_u32() == local && place.projection.is_empty() => {
+            Some(info.name.to_string())
+        }
+        _ => None,
+    })
+}
+
+// Name of a variable captured by the closure held in a local, and the number of dereferences
+// from the field of the closure to the variable: 1 when it is captured by reference
+pub fn upvar_name(body: &Body, local: u32, field: u32) -> Option<(String, usize)> {
+    body.var_debug_info.iter().find_map(|info| match info.value {
+        VarDebugInfoContents::Place(place) if place.local.as_u32() == local => {
+            let projection: Vec<_> = place.projection.iter().skip_while(|elem| *elem == ProjectionElem::Deref).collect();
+            match projection.split_first() {
+                Some((ProjectionElem::Field(index, _), derefs)) if index.as_u32() == field && derefs.iter().all(|elem| *elem == ProjectionElem::Deref) => {
+                    Some((info.name.to_string(), derefs.len()))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
@@ -0,0 +1,48 @@
+use log::LevelFilter;
+use static_alias_analyzer::analyzer::{AliasBackend, AnalysisMode};
+use static_alias_analyzer::cli::{parse, OutputFormat};
+use static_alias_analyzer::diagnostics::Checker;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+fn flags_with_equals_sign() {
+    let options = parse(args("--entry=main --entry=utils::read --format=json --checkers=mutable-arguments \
+        --path-depth=2 --points-to=steensgaard -v src/lib.rs --edition=2021")).unwrap();
+    assert_eq!(options.analysis.mode, AnalysisMode::Functions(args("main utils::read")));
+    assert_eq!(options.format, OutputFormat::Json);
+    assert_eq!(options.analysis.checkers, vec![Checker::MutableArguments]);
+    assert_eq!(options.analysis.path_depth, 2);
+    assert_eq!(options.analysis.alias_backend, AliasBackend::Steensgaard);
+    assert_eq!(options.verbosity, LevelFilter::Info);
+    assert_eq!(options.forwarded_args, args("src/lib.rs --edition=2021"));
+}
+
+#[test]
+fn flags_with_separate_value() {
+    let options = parse(args("--entry main --format dot --path-depth 3 -- --entry x")).unwrap();
+    assert_eq!(options.analysis.mode, AnalysisMode::Functions(args("main")));
+    assert_eq!(options.format, OutputFormat::Dot);
+    assert_eq!(options.analysis.path_depth, 3);
+    assert_eq!(options.forwarded_args, args("--entry x"));
+}
+
+#[test]
+fn invalid_values() {
+    assert!(parse(args("--format=yaml")).is_err());
+    assert!(parse(args("--path-depth=")).is_err());
+    assert!(parse(args("--entry")).is_err());
+}
+
+#[test]
+fn options_round_trip() {
+    let options = parse(args("--unsafe --checkers=stacked-borrows --points-to=steensgaard -q")).unwrap();
+    let parsed = parse(options.to_args()).unwrap();
+    assert_eq!(parsed.analysis.mode, options.analysis.mode);
+    assert_eq!(parsed.analysis.checkers, options.analysis.checkers);
+    assert_eq!(parsed.analysis.alias_backend, options.analysis.alias_backend);
+    assert_eq!(parsed.verbosity, options.verbosity);
+    assert!(parsed.forwarded_args.is_empty());
+}